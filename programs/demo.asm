@@ -0,0 +1,34 @@
+; Demo program loaded by the GUI at startup
+; Expected outputs: 99, 42, 15
+
+; Test 1: Basic arithmetic and BNE
+        LDA # 5         ; Load 5 into A
+        SUB # 5         ; Subtract 5, A = 0, ZF = 1
+        BNE wrong1      ; Should NOT jump, ZF = 1
+        LDA # 99        ; This should execute
+        OUT             ; Output 99
+        JMP test2       ; Jump to next test
+; If BNE incorrectly jumped here:
+wrong1: LDA # 255       ; This should NOT execute
+        OUT             ; Should not output 255
+
+; Test 2: JPZ (jump if zero)
+test2:  LDA # 10
+        CMP # 0         ; ZF = 0
+        JPZ wrong2      ; Should NOT jump, ZF = 0
+        LDA # 0
+        CMP # 0         ; ZF = 1
+        JPZ test3       ; Should jump, ZF = 1
+        LDA # 111       ; Should NOT execute
+wrong2: OUT             ; Should NOT output 111
+        HLT             ; Should NOT halt here
+
+; Test 3: Successful jump lands here
+test3:  LDA # 42        ; This should execute
+        OUT             ; Output 42
+
+; Test 4: Simple addition
+        LDA # 10
+        ADD # 5         ; A = 15
+        OUT             ; Output 15
+        HLT             ; End
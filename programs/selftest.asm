@@ -0,0 +1,69 @@
+; Test program that exercises most instructions
+; Expected outputs: 110, 60, 70, 170, 0
+
+; Test 1: Store values in memory for later use
+        LDA # 100       ; Load 100 into A
+        STA hundred     ; Store at address 240
+        LDA # 50        ; Load 50 into A
+        STA fifty       ; Store at address 241
+
+; Test 2: ADD $ (add from memory)
+        LDA # 10        ; Load 10 into A
+        ADD $ hundred   ; Add value from address 240 (100)
+        OUT             ; should output 110
+
+; Test 3: SUB $ (subtract from memory)
+        SUB $ fifty     ; Subtract value from address 241 (50)
+        OUT             ; should output 60
+
+; Test 4: LDB $ (load B from memory)
+        LDB $ fifty     ; Load B from address 241 (50)
+        ADD # 10        ; Add 10 to A (should be 70)
+        OUT             ; should output 70, B should be 10 from last operation
+
+; Test 5: CMP $ (compare with memory)
+        LDA # 100       ; Load 100 into A
+        CMP $ hundred   ; Compare with address 240 (100)
+        JPZ test6       ; Jump if equal (should jump)
+        OUT             ; should be skipped
+
+; Test 6: Test overflow with ADD $
+test6:  LDA # 255       ; Load 255 into A
+        STA max         ; Store at address 242
+        ADD $ max       ; Add from address 242 (255+255=254 with carry)
+        JPC test7       ; Jump if carry set
+        OUT             ; should be skipped
+
+; Test 7: Test underflow with SUB $
+test7:  LDA # 5         ; Load 5 into A
+        STA five        ; Store at address 243
+        LDA # 3         ; Load 3 into A
+        SUB $ five      ; Subtract from address 243 (3-5 = underflow)
+        JPC test8       ; Jump if borrow/carry
+        OUT             ; should be skipped
+
+; Test 8: JMP (unconditional jump)
+test8:  LDA # 170       ; Load 170 into A
+        JMP test9       ; Jump over the next OUT
+        OUT             ; should be skipped
+
+; Test 9: NOP (does nothing)
+test9:  NOP
+        NOP
+        NOP
+        OUT             ; should output 170
+
+; Test 10: Final test - multiple operations
+        LDA # 0         ; Load 0
+        CMP # 0         ; Compare with 0 (should set ZF)
+        BNE done        ; Jump if not equal (shouldn't jump)
+        OUT             ; should output 0
+
+done:   HLT
+
+; Scratch variables written by the tests
+        .org 240
+hundred: .byte 0
+fifty:  .byte 0
+max:    .byte 0
+five:   .byte 0
@@ -0,0 +1,643 @@
+use crate::isa::{INSTRUCTIONS, Instruction, Operand};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(String),
+    // `"text"` with escapes already resolved
+    Str(String),
+    // `#`, immediate operand
+    Hash,
+    // `$`, memory operand
+    Dollar,
+    Comma,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    // 1-based column of the first character
+    column: usize,
+}
+
+impl Token {
+    fn text(&self) -> String {
+        match &self.kind {
+            TokenKind::Word(word) => word.clone(),
+            TokenKind::Str(text) => format!("\"{}\"", text),
+            TokenKind::Hash => "#".to_string(),
+            TokenKind::Dollar => "$".to_string(),
+            TokenKind::Comma => ",".to_string(),
+        }
+    }
+}
+
+type LineError = (usize, String);
+
+// Split a line into tokens, dropping everything after a `;` comment
+fn tokenize(line: &str) -> Result<Vec<Token>, LineError> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+
+    while let Some(&(i, c)) = chars.peek() {
+        let column = line[..i].chars().count() + 1;
+        let kind = match c {
+            ';' => break,
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '#' => {
+                chars.next();
+                TokenKind::Hash
+            }
+            '$' => {
+                chars.next();
+                TokenKind::Dollar
+            }
+            ',' => {
+                chars.next();
+                TokenKind::Comma
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, 'n')) => text.push('\n'),
+                            Some((_, '0')) => text.push('\0'),
+                            Some((_, c @ ('"' | '\\'))) => text.push(c),
+                            _ => return Err((column, "invalid escape in string".to_string())),
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => return Err((column, "unterminated string".to_string())),
+                    }
+                }
+                TokenKind::Str(text)
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, ';' | '#' | '$' | ',' | '"') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                TokenKind::Word(word)
+            }
+        };
+        tokens.push(Token { kind, column });
+    }
+
+    Ok(tokens)
+}
+
+// Decimal, 0x hex or 0b binary literal
+pub fn parse_number(text: &str) -> Option<i32> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        i32::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        i32::from_str_radix(bin, 2).ok()
+    } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn is_symbol_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// An operand that may refer to labels and constants, e.g. `table+2`
+#[derive(Debug, Clone)]
+struct Expr {
+    text: String,
+    column: usize,
+}
+
+impl Expr {
+    // Sum of numbers and symbols joined by `+` and `-`, wrapped to a byte
+    fn evaluate(&self, symbols: &HashMap<String, u8>) -> Result<u8, LineError> {
+        let invalid = || (self.column, format!("invalid expression '{}'", self.text));
+
+        let mut total: i32 = 0;
+        let mut sign = 1;
+        let mut rest = self.text.as_str();
+        if let Some(negated) = rest.strip_prefix('-') {
+            sign = -1;
+            rest = negated;
+        }
+        loop {
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let term = &rest[..end];
+            let value = if term.is_empty() {
+                return Err(invalid());
+            } else if let Some(value) = parse_number(term) {
+                value
+            } else if let Some(&value) = symbols.get(term) {
+                value as i32
+            } else if is_symbol_name(term) {
+                return Err((self.column, format!("undefined symbol '{}'", term)));
+            } else {
+                return Err((self.column, format!("'{}' is not a number", term)));
+            };
+            total += sign * value;
+
+            if end == rest.len() {
+                break;
+            }
+            sign = if rest[end..].starts_with('-') { -1 } else { 1 };
+            rest = &rest[end + 1..];
+        }
+
+        if !(-255..=255).contains(&total) {
+            return Err((
+                self.column,
+                format!("'{}' does not fit in a byte ({})", self.text, total),
+            ));
+        }
+        Ok(total as u8)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Instruction { opcode: u8, operand: Option<Expr> },
+    Bytes(Vec<Expr>),
+    Raw(Vec<u8>),
+}
+
+impl Item {
+    fn size(&self) -> usize {
+        match self {
+            Item::Instruction { operand, .. } => 1 + operand.is_some() as usize,
+            Item::Bytes(values) => values.len(),
+            Item::Raw(bytes) => bytes.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Statement {
+    line: usize,
+    column: usize,
+    address: usize,
+    item: Item,
+}
+
+// Describe the operand forms a mnemonic accepts, for error messages
+fn expected_forms(mnemonic: &str) -> String {
+    let forms: Vec<String> = INSTRUCTIONS
+        .iter()
+        .filter(|inst| inst.mnemonic.eq_ignore_ascii_case(mnemonic))
+        .map(|inst| match inst.operand {
+            Operand::None => "no operand".to_string(),
+            Operand::Memory => "'$ address'".to_string(),
+            Operand::Immediate => "'# value'".to_string(),
+            Operand::Address => "an address".to_string(),
+        })
+        .collect();
+    format!(
+        "{} takes {}",
+        mnemonic.to_ascii_uppercase(),
+        forms.join(" or ")
+    )
+}
+
+// Assemble source text into a memory image, one statement per line:
+//
+//   .equ COUNT 3       ; named constant
+//           LDA # COUNT
+//   loop:   SUB # 1    ; labels end with a colon
+//           BNE loop
+//           STA result
+//           HLT
+//   .org 0xF0          ; continue assembling at address 0xF0
+//   result: .byte 0
+//   table:  .db 1, 2, COUNT+1
+//   name:   .str "R-SAP-1"  ; string bytes followed by a 0
+//
+// `$ addr` reads from memory and `# value` is immediate; stores and jumps take
+// a plain address. Numbers may be decimal, 0x hex or 0b binary, and operands
+// may add or subtract symbols. Every error found is reported.
+pub fn assemble(source: &str) -> Result<[u8; 256], Vec<AsmError>> {
+    let mut symbols: HashMap<String, u8> = HashMap::new();
+    let mut statements = Vec::new();
+    let mut errors = Vec::new();
+    let mut address = 0usize;
+
+    // First pass: work out where everything goes and collect the symbols
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let result = tokenize(line)
+            .and_then(|tokens| parse_line(&tokens, line_number, &mut address, &mut symbols));
+        match result {
+            Ok(Some(statement)) => {
+                address += statement.item.size();
+                statements.push(statement);
+            }
+            Ok(None) => {}
+            Err((column, message)) => errors.push(AsmError {
+                line: line_number,
+                column,
+                message,
+            }),
+        }
+    }
+
+    // Second pass: encode with every label known
+    let mut memory = [0u8; 256];
+    let mut written = [false; 256];
+    for statement in &statements {
+        let bytes = match encode(&statement.item, &symbols) {
+            Ok(bytes) => bytes,
+            Err((column, message)) => {
+                errors.push(AsmError {
+                    line: statement.line,
+                    column,
+                    message,
+                });
+                continue;
+            }
+        };
+        for (offset, byte) in bytes.into_iter().enumerate() {
+            let target = statement.address + offset;
+            if target >= memory.len() {
+                errors.push(AsmError {
+                    line: statement.line,
+                    column: statement.column,
+                    message: "program does not fit in 256 bytes of memory".to_string(),
+                });
+                break;
+            }
+            if written[target] {
+                errors.push(AsmError {
+                    line: statement.line,
+                    column: statement.column,
+                    message: format!("overwrites address {} assembled earlier", target),
+                });
+                break;
+            }
+            memory[target] = byte;
+            written[target] = true;
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(memory)
+    } else {
+        errors.sort_by_key(|error| (error.line, error.column));
+        Err(errors)
+    }
+}
+
+// Assemble a single instruction without labels, for entering code by hand
+pub fn assemble_line(line: &str) -> Result<Vec<u8>, String> {
+    let tokens = tokenize(line).map_err(|(_, message)| message)?;
+    let Some(first) = tokens.first() else {
+        return Err("nothing to assemble".to_string());
+    };
+    let TokenKind::Word(mnemonic) = &first.kind else {
+        return Err(format!("unexpected '{}'", first.text()));
+    };
+    parse_instruction(first, mnemonic, &tokens[1..])
+        .and_then(|item| encode(&item, &HashMap::new()))
+        .map_err(|(_, message)| message)
+}
+
+fn define_symbol(
+    symbols: &mut HashMap<String, u8>,
+    token: &Token,
+    name: &str,
+    value: u8,
+) -> Result<(), LineError> {
+    if !is_symbol_name(name) || Instruction::is_mnemonic(name) {
+        return Err((
+            token.column,
+            format!("'{}' is not a valid symbol name", name),
+        ));
+    }
+    if symbols.insert(name.to_string(), value).is_some() {
+        return Err((token.column, format!("'{}' is already defined", name)));
+    }
+    Ok(())
+}
+
+// Parse one line in the first pass. Labels, `.equ` and `.org` take effect
+// immediately; anything that emits bytes is returned for the second pass.
+fn parse_line(
+    tokens: &[Token],
+    line: usize,
+    address: &mut usize,
+    symbols: &mut HashMap<String, u8>,
+) -> Result<Option<Statement>, LineError> {
+    let mut tokens = tokens;
+
+    if let Some(Token {
+        kind: TokenKind::Word(word),
+        ..
+    }) = tokens.first()
+        && let Some(label) = word.strip_suffix(':')
+    {
+        if *address > 255 {
+            return Err((
+                tokens[0].column,
+                "label is past the end of memory".to_string(),
+            ));
+        }
+        define_symbol(symbols, &tokens[0], label, *address as u8)?;
+        tokens = &tokens[1..];
+    }
+
+    let Some(first) = tokens.first() else {
+        return Ok(None);
+    };
+    let TokenKind::Word(word) = &first.kind else {
+        return Err((first.column, format!("unexpected '{}'", first.text())));
+    };
+    let rest = &tokens[1..];
+
+    let item = match word.to_ascii_lowercase().as_str() {
+        ".equ" => {
+            let (name_token, value_tokens) = rest
+                .split_first()
+                .ok_or_else(|| (first.column, ".equ needs a name and a value".to_string()))?;
+            let value_tokens = match value_tokens.first().map(|t| &t.kind) {
+                Some(TokenKind::Comma) => &value_tokens[1..],
+                _ => value_tokens,
+            };
+            let value = single_expr(first, value_tokens)?.evaluate(symbols)?;
+            define_symbol(symbols, name_token, &name_token.text(), value)?;
+            return Ok(None);
+        }
+        ".org" => {
+            *address = single_expr(first, rest)?.evaluate(symbols)? as usize;
+            return Ok(None);
+        }
+        ".byte" | ".db" => Item::Bytes(expr_list(first, rest)?),
+        ".str" => match rest {
+            [
+                Token {
+                    kind: TokenKind::Str(text),
+                    column,
+                },
+            ] => {
+                if !text.is_ascii() {
+                    return Err((*column, "strings may only contain ASCII".to_string()));
+                }
+                let mut bytes = text.clone().into_bytes();
+                bytes.push(0);
+                Item::Raw(bytes)
+            }
+            _ => return Err((first.column, ".str needs one quoted string".to_string())),
+        },
+        directive if directive.starts_with('.') => {
+            return Err((first.column, format!("unknown directive '{}'", word)));
+        }
+        _ => parse_instruction(first, word, rest)?,
+    };
+
+    Ok(Some(Statement {
+        line,
+        column: first.column,
+        address: *address,
+        item,
+    }))
+}
+
+fn single_expr(directive: &Token, tokens: &[Token]) -> Result<Expr, LineError> {
+    match tokens {
+        [
+            Token {
+                kind: TokenKind::Word(text),
+                column,
+            },
+        ] => Ok(Expr {
+            text: text.clone(),
+            column: *column,
+        }),
+        [] => Err((
+            directive.column,
+            format!("{} needs a value", directive.text()),
+        )),
+        [_, extra, ..] => Err((extra.column, format!("unexpected '{}'", extra.text()))),
+        [other] => Err((other.column, format!("unexpected '{}'", other.text()))),
+    }
+}
+
+// Comma separated values for `.byte`; strings expand to their characters
+fn expr_list(directive: &Token, tokens: &[Token]) -> Result<Vec<Expr>, LineError> {
+    let mut values = Vec::new();
+    let mut expect_value = true;
+    for token in tokens {
+        match (&token.kind, expect_value) {
+            (TokenKind::Word(text), true) => {
+                values.push(Expr {
+                    text: text.clone(),
+                    column: token.column,
+                });
+                expect_value = false;
+            }
+            (TokenKind::Str(text), true) => {
+                if !text.is_ascii() {
+                    return Err((token.column, "strings may only contain ASCII".to_string()));
+                }
+                values.extend(text.bytes().map(|byte| Expr {
+                    text: byte.to_string(),
+                    column: token.column,
+                }));
+                expect_value = false;
+            }
+            (TokenKind::Comma, false) => expect_value = true,
+            (TokenKind::Comma, true) => {
+                return Err((token.column, "missing value before ','".to_string()));
+            }
+            _ => return Err((token.column, format!("unexpected '{}'", token.text()))),
+        }
+    }
+    match tokens.last() {
+        None => Err((
+            directive.column,
+            format!("{} needs at least one value", directive.text()),
+        )),
+        Some(last) if expect_value => Err((last.column + 1, "missing value after ','".to_string())),
+        _ => Ok(values),
+    }
+}
+
+fn parse_instruction(first: &Token, mnemonic: &str, rest: &[Token]) -> Result<Item, LineError> {
+    if !Instruction::is_mnemonic(mnemonic) {
+        return Err((
+            first.column,
+            format!("unknown instruction '{}'", first.text()),
+        ));
+    }
+
+    let (operand, value_tokens) = match rest.first().map(|token| &token.kind) {
+        None => (Operand::None, rest),
+        Some(TokenKind::Hash) => (Operand::Immediate, &rest[1..]),
+        Some(TokenKind::Dollar) => {
+            // Stores and jumps also accept `$` in front of their address
+            let operand = if Instruction::lookup(mnemonic, Operand::Address).is_some() {
+                Operand::Address
+            } else {
+                Operand::Memory
+            };
+            (operand, &rest[1..])
+        }
+        Some(_) => (Operand::Address, rest),
+    };
+
+    let instruction = Instruction::lookup(mnemonic, operand)
+        .ok_or_else(|| (first.column, expected_forms(mnemonic)))?;
+
+    let operand = if operand == Operand::None {
+        None
+    } else if value_tokens.is_empty() {
+        let last = rest.last().unwrap_or(first);
+        return Err((last.column + 1, "missing operand".to_string()));
+    } else {
+        Some(single_expr(first, value_tokens)?)
+    };
+
+    Ok(Item::Instruction {
+        opcode: instruction.opcode,
+        operand,
+    })
+}
+
+fn encode(item: &Item, symbols: &HashMap<String, u8>) -> Result<Vec<u8>, LineError> {
+    match item {
+        Item::Instruction { opcode, operand } => {
+            let mut bytes = vec![*opcode];
+            if let Some(operand) = operand {
+                bytes.push(operand.evaluate(symbols)?);
+            }
+            Ok(bytes)
+        }
+        Item::Bytes(values) => values.iter().map(|value| value.evaluate(symbols)).collect(),
+        Item::Raw(bytes) => Ok(bytes.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(source: &str) -> Vec<(usize, usize, String)> {
+        assemble(source)
+            .expect_err("source has errors")
+            .into_iter()
+            .map(|error| (error.line, error.column, error.message))
+            .collect()
+    }
+
+    #[test]
+    fn encodes_addressing_modes() {
+        let memory = assemble("LDA # 5\nADD $ 0x10\nSTA $ 0b11\nOUT\nHLT").unwrap();
+        assert_eq!(memory[..9], [0x20, 5, 0x50, 0x10, 0x90, 3, 0xF3, 0xFF, 0]);
+    }
+
+    #[test]
+    fn resolves_labels_and_forward_references() {
+        let source = "
+start:  LDA # 1
+        JMP end
+        .byte 7
+end:    JMP start
+";
+        let memory = assemble(source).unwrap();
+        assert_eq!(memory[..7], [0x20, 1, 0xA0, 5, 7, 0xA0, 0]);
+    }
+
+    #[test]
+    fn evaluates_constants_and_data_directives() {
+        let source = "
+.equ COUNT, 3
+        LDA # COUNT+1
+table:  .db COUNT-1, table, 0xFF
+        .str \"Hi\\n\"
+";
+        let memory = assemble(source).unwrap();
+        assert_eq!(memory[..9], [0x20, 4, 2, 2, 0xFF, b'H', b'i', b'\n', 0]);
+    }
+
+    #[test]
+    fn org_moves_the_location_counter() {
+        let memory = assemble("JMP main\n.org 0x10\nmain: HLT").unwrap();
+        assert_eq!(memory[..2], [0xA0, 0x10]);
+        assert_eq!(memory[0x10], 0xFF);
+    }
+
+    #[test]
+    fn org_overlap_is_an_error() {
+        let source = ".org 4\n.byte 1, 2\n.org 3\n  .byte 3, 4";
+        assert_eq!(
+            errors(source),
+            [(4, 3, "overwrites address 4 assembled earlier".to_string())]
+        );
+    }
+
+    #[test]
+    fn program_past_the_end_of_memory_is_an_error() {
+        assert_eq!(
+            errors(".org 255\nLDA # 1"),
+            [(
+                2,
+                1,
+                "program does not fit in 256 bytes of memory".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn reports_every_error_with_its_position() {
+        let source = "LDA # 1\n  FOO 3\n  JMP missing\nLDA #\nx: OUT\nx: OUT";
+        assert_eq!(
+            errors(source),
+            [
+                (2, 3, "unknown instruction 'FOO'".to_string()),
+                (3, 7, "undefined symbol 'missing'".to_string()),
+                (4, 6, "missing operand".to_string()),
+                (6, 1, "'x' is already defined".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_operands_an_instruction_does_not_take() {
+        assert_eq!(
+            errors("OUT # 1\nSTA # 1\nLDA # 256"),
+            [
+                (1, 1, "OUT takes no operand".to_string()),
+                (2, 1, "STA takes an address".to_string()),
+                (3, 7, "'256' does not fit in a byte (256)".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn assembles_a_single_line() {
+        assert_eq!(assemble_line("ADD # 5"), Ok(vec![0x60, 5]));
+        assert_eq!(assemble_line("out"), Ok(vec![0xF3]));
+        assert!(assemble_line("JMP loop").is_err());
+    }
+}
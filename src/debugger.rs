@@ -0,0 +1,445 @@
+use crate::assembler;
+use crate::emulator::Sap1;
+
+// Why the emulator stopped in the middle of a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreakReason {
+    // PC reached a breakpoint address at the start of an instruction
+    Breakpoint(u8),
+    // A watched RAM address was read (RO without II) or written (RI)
+    Watchpoint {
+        address: u8,
+        access: Access,
+        value: u8,
+    },
+    // A breakpoint condition became true
+    Condition(String),
+    // More than one signal drove the bus, with halt on contention enabled
+    BusContention(BusContention),
+    // An instruction ran past T7 without PR, with the halt or trap policy
+    IllegalOpcode(u8),
+}
+
+impl std::fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BreakReason::Breakpoint(address) => write!(f, "breakpoint at address {}", address),
+            BreakReason::Watchpoint {
+                address,
+                access: Access::Read,
+                value,
+            } => write!(f, "read {} from address {}", value, address),
+            BreakReason::Watchpoint {
+                address,
+                access: Access::Write,
+                value,
+            } => write!(f, "wrote {} to address {}", value, address),
+            BreakReason::Condition(text) => write!(f, "condition {}", text),
+            BreakReason::BusContention(contention) => write!(f, "{}", contention),
+            BreakReason::IllegalOpcode(opcode) => {
+                write!(f, "illegal opcode 0x{:02X} ran past T7", opcode)
+            }
+        }
+    }
+}
+
+// A tick where several outputs drove the bus at once. On the breadboard that
+// shorts the outputs together; the emulator lets the last driver win.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BusContention {
+    pub opcode: u8,
+    pub t_step: u8,
+    pub drivers: Vec<&'static str>,
+}
+
+impl std::fmt::Display for BusContention {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bus contention between {} at opcode 0x{:02X} T{}",
+            self.drivers.join(", "),
+            self.opcode,
+            self.t_step
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// Which RAM accesses stop the clock for a watched address
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    pub fn matches(self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        }
+    }
+
+    // Parse "r", "w" or "rw", as typed in the terminal
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.to_ascii_lowercase().as_str() {
+            "r" => Some(Watchpoint {
+                read: true,
+                write: false,
+            }),
+            "w" => Some(Watchpoint {
+                read: false,
+                write: true,
+            }),
+            "rw" | "wr" => Some(Watchpoint {
+                read: true,
+                write: true,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.read {
+            write!(f, "r")?;
+        }
+        if self.write {
+            write!(f, "w")?;
+        }
+        Ok(())
+    }
+}
+
+// A breakpoint condition such as "A == 0 && CF", checked after every clock tick.
+// It stops the clock when it changes from false to true, so a run can be
+// continued while the condition still holds.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub text: String,
+    expr: Expr,
+    was_true: bool,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(format!("unexpected '{}'", token));
+        }
+        Ok(Condition {
+            text: text.trim().to_string(),
+            expr,
+            was_true: false,
+        })
+    }
+
+    // Evaluate against the machine, returning true when the condition has just become true
+    pub fn update(&mut self, sap1: &Sap1) -> bool {
+        let is_true = self.expr.evaluate(sap1) != 0;
+        let triggered = is_true && !self.was_true;
+        self.was_true = is_true;
+        triggered
+    }
+
+    // Take the machine's current value as the previous one, after the state
+    // was changed other than by a clock tick
+    pub fn sync(&mut self, sap1: &Sap1) {
+        self.was_true = self.expr.evaluate(sap1) != 0;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Register {
+    A,
+    B,
+    Out,
+    Carry,
+    Zero,
+    Bus,
+    TStep,
+}
+
+impl Register {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "A" => Some(Register::A),
+            "B" => Some(Register::B),
+            "OUT" => Some(Register::Out),
+            "CF" => Some(Register::Carry),
+            "ZF" => Some(Register::Zero),
+            "BUS" => Some(Register::Bus),
+            "T" => Some(Register::TStep),
+            _ => None,
+        }
+    }
+
+    fn read(self, sap1: &Sap1) -> i32 {
+        match self {
+            Register::A => sap1.reg_a as i32,
+            Register::B => sap1.reg_b as i32,
+            Register::Out => sap1.output as i32,
+            Register::Carry => sap1.cf as i32,
+            Register::Zero => sap1.zf as i32,
+            Register::Bus => sap1.bus as i32,
+            Register::TStep => sap1.t_step as i32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// Conditions evaluate to integers, comparisons and logic to 0 or 1
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i32),
+    Register(Register),
+    Not(Box<Expr>),
+    Compare(Compare, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn evaluate(&self, sap1: &Sap1) -> i32 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Register(register) => register.read(sap1),
+            Expr::Not(expr) => (expr.evaluate(sap1) == 0) as i32,
+            Expr::Compare(compare, left, right) => {
+                let (left, right) = (left.evaluate(sap1), right.evaluate(sap1));
+                let result = match compare {
+                    Compare::Eq => left == right,
+                    Compare::Ne => left != right,
+                    Compare::Lt => left < right,
+                    Compare::Le => left <= right,
+                    Compare::Gt => left > right,
+                    Compare::Ge => left >= right,
+                };
+                result as i32
+            }
+            Expr::And(left, right) => {
+                (left.evaluate(sap1) != 0 && right.evaluate(sap1) != 0) as i32
+            }
+            Expr::Or(left, right) => (left.evaluate(sap1) != 0 || right.evaluate(sap1) != 0) as i32,
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(word);
+        } else {
+            chars.next();
+            let next = chars.peek().copied();
+            let token = match (c, next) {
+                ('=', Some('=')) | ('!', Some('=')) | ('<', Some('=')) | ('>', Some('=')) => {
+                    chars.next();
+                    format!("{}=", c)
+                }
+                ('&', Some('&')) | ('|', Some('|')) => {
+                    chars.next();
+                    format!("{}{}", c, c)
+                }
+                ('!' | '<' | '>' | '(' | ')', _) => c.to_string(),
+                _ => return Err(format!("unexpected character '{}'", c)),
+            };
+            tokens.push(token);
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| "unexpected end of condition".to_string())?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some("||") {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.compare()?;
+        while self.peek() == Some("&&") {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.compare()?));
+        }
+        Ok(expr)
+    }
+
+    fn compare(&mut self) -> Result<Expr, String> {
+        let left = self.unary()?;
+        let compare = match self.peek() {
+            Some("==") => Compare::Eq,
+            Some("!=") => Compare::Ne,
+            Some("<") => Compare::Lt,
+            Some("<=") => Compare::Le,
+            Some(">") => Compare::Gt,
+            Some(">=") => Compare::Ge,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.unary()?;
+        Ok(Expr::Compare(compare, Box::new(left), Box::new(right)))
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let token = self.next()?;
+        match token.as_str() {
+            "!" => Ok(Expr::Not(Box::new(self.unary()?))),
+            "(" => {
+                let expr = self.or()?;
+                match self.next()?.as_str() {
+                    ")" => Ok(expr),
+                    other => Err(format!("expected ')' but found '{}'", other)),
+                }
+            }
+            _ => {
+                if let Some(register) = Register::from_name(&token) {
+                    Ok(Expr::Register(register))
+                } else if let Some(value) = assembler::parse_number(&token) {
+                    Ok(Expr::Number(value))
+                } else {
+                    Err(format!(
+                        "unknown name '{}', expected A, B, OUT, CF, ZF, BUS, T or a number",
+                        token
+                    ))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn holds(text: &str, sap1: &Sap1) -> bool {
+        Condition::parse(text).unwrap().update(sap1)
+    }
+
+    fn parse_error(text: &str) -> String {
+        Condition::parse(text).expect_err("condition is invalid")
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let mut sap1 = Sap1::new();
+        sap1.reg_a = 1;
+        assert!(holds("A == 1 || B == 2 && CF", &sap1));
+        assert!(!holds("(A == 1 || B == 2) && CF", &sap1));
+        sap1.reg_a = 0;
+        sap1.reg_b = 2;
+        assert!(!holds("A == 1 || B == 2 && CF", &sap1));
+        sap1.cf = true;
+        assert!(holds("A == 1 || B == 2 && CF", &sap1));
+    }
+
+    #[test]
+    fn not_applies_to_the_next_operand() {
+        let mut sap1 = Sap1::new();
+        sap1.reg_a = 5;
+        sap1.zf = false;
+        assert!(holds("!ZF && A >= 5", &sap1));
+        assert!(holds("!(A < 5) && !(A > 5)", &sap1));
+        assert!(!holds("!A == 1", &sap1));
+        assert!(holds("a != 0x05 || out <= 0b0", &sap1));
+    }
+
+    #[test]
+    fn triggers_when_it_becomes_true() {
+        let mut sap1 = Sap1::new();
+        let mut condition = Condition::parse("A == 3").unwrap();
+        assert!(!condition.update(&sap1));
+        sap1.reg_a = 3;
+        assert!(condition.update(&sap1));
+        assert!(!condition.update(&sap1));
+        sap1.reg_a = 4;
+        assert!(!condition.update(&sap1));
+        sap1.reg_a = 3;
+        assert!(condition.update(&sap1));
+        condition.sync(&sap1);
+        assert!(!condition.update(&sap1));
+    }
+
+    #[test]
+    fn added_condition_waits_for_a_change() {
+        let mut sap1 = Sap1::new();
+        sap1.reg_a = 3;
+        sap1.add_condition(Condition::parse("A == 3").unwrap());
+        sap1.clock_tick();
+        assert_eq!(sap1.break_reason, None);
+        sap1.reg_a = 4;
+        sap1.clock_tick();
+        sap1.reg_a = 3;
+        sap1.clock_tick();
+        assert_eq!(
+            sap1.break_reason,
+            Some(BreakReason::Condition("A == 3".to_string()))
+        );
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        assert_eq!(parse_error("A =="), "unexpected end of condition");
+        assert_eq!(parse_error("(A == 1"), "unexpected end of condition");
+        assert_eq!(parse_error("A == 1)"), "unexpected ')'");
+        assert_eq!(parse_error("A == 1 B"), "unexpected 'B'");
+        assert_eq!(parse_error("(A == 1 B)"), "expected ')' but found 'B'");
+        assert_eq!(parse_error("A = 1"), "unexpected character '='");
+        assert_eq!(
+            parse_error("PC > 3"),
+            "unknown name 'PC', expected A, B, OUT, CF, ZF, BUS, T or a number"
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use crate::emulator::ControlWord;
+use crate::microcode::{MicrocodeRom, STEPS};
+
+// Number of address lines used by each field of the EEPROM address
+const FLAG_BITS: u32 = 2;
+const STEP_BITS: u32 = 3;
+const OPCODE_BITS: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressField {
+    // Flag register outputs: ZF on the lower line, CF on the upper one
+    Flags,
+    // T-step counter
+    Step,
+    // Instruction register
+    Opcode,
+}
+
+impl AddressField {
+    fn bits(self) -> u32 {
+        match self {
+            AddressField::Flags => FLAG_BITS,
+            AddressField::Step => STEP_BITS,
+            AddressField::Opcode => OPCODE_BITS,
+        }
+    }
+}
+
+// How the flags, T-step and opcode are wired to the EEPROM address lines,
+// listed from A0 upwards
+#[derive(Debug, Clone, Copy)]
+pub struct EepromLayout {
+    pub fields: [AddressField; 3],
+}
+
+impl Default for EepromLayout {
+    fn default() -> Self {
+        EepromLayout {
+            fields: [
+                AddressField::Flags,
+                AddressField::Step,
+                AddressField::Opcode,
+            ],
+        }
+    }
+}
+
+impl EepromLayout {
+    // Parse a comma separated field order such as "step,flags,opcode"
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut fields = Vec::new();
+        for name in text.split(',') {
+            let field = match name.trim().to_ascii_lowercase().as_str() {
+                "flags" => AddressField::Flags,
+                "step" => AddressField::Step,
+                "opcode" => AddressField::Opcode,
+                other => return Err(format!("unknown address field '{}'", other)),
+            };
+            if fields.contains(&field) {
+                return Err(format!("address field '{}' listed twice", name.trim()));
+            }
+            fields.push(field);
+        }
+        let fields: [AddressField; 3] = fields
+            .try_into()
+            .map_err(|_| "layout must list flags, step and opcode".to_string())?;
+        Ok(EepromLayout { fields })
+    }
+
+    fn shift(&self, field: AddressField) -> u32 {
+        self.fields
+            .iter()
+            .take_while(|&&f| f != field)
+            .map(|f| f.bits())
+            .sum()
+    }
+
+    pub fn address(&self, opcode: u8, step: u8, cf: bool, zf: bool) -> usize {
+        let flags = ((cf as usize) << 1) | zf as usize;
+        (flags << self.shift(AddressField::Flags))
+            | ((step as usize) << self.shift(AddressField::Step))
+            | ((opcode as usize) << self.shift(AddressField::Opcode))
+    }
+}
+
+// Number of 8-bit EEPROMs needed to hold every control signal
+pub fn chip_count() -> usize {
+    ControlWord::SIGNALS.div_ceil(8)
+}
+
+// The signal on each data line of every chip, D7 first. Unused lines on the
+// last chip are `None`.
+pub fn pinout() -> Vec<[Option<&'static str>; 8]> {
+    let names = ControlWord::signal_names();
+    (0..chip_count())
+        .map(|chip| std::array::from_fn(|line| names.get(chip * 8 + line).copied()))
+        .collect()
+}
+
+// Build one image per EEPROM. Chip 0 drives the first eight signals (HLT..AO),
+// chip 1 the next eight, and so on; unused data lines on the last chip read 0.
+pub fn build_images(rom: &MicrocodeRom, layout: &EepromLayout) -> Vec<Vec<u8>> {
+    let size = 1 << (FLAG_BITS + STEP_BITS + OPCODE_BITS);
+    let chips = chip_count();
+    let mut images = vec![vec![0u8; size]; chips];
+
+    for opcode in 0..=255u8 {
+        for step in 0..STEPS as u8 {
+            for cf in [false, true] {
+                for zf in [false, true] {
+                    // Left align the signals so HLT sits in the top bit of chip 0
+                    let bits = rom.get(opcode, step, cf, zf).to_bits()
+                        << (chips * 8 - ControlWord::SIGNALS);
+                    let address = layout.address(opcode, step, cf, zf);
+                    for (chip, image) in images.iter_mut().enumerate() {
+                        image[address] = (bits >> ((chips - 1 - chip) * 8)) as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    images
+}
+
+// Write `<prefix>_<chip>.bin` for every EEPROM, returning the file names
+pub fn export(
+    rom: &MicrocodeRom,
+    layout: &EepromLayout,
+    prefix: &str,
+) -> std::io::Result<Vec<String>> {
+    let mut paths = Vec::new();
+    for (chip, image) in build_images(rom, layout).into_iter().enumerate() {
+        let path = format!("{}_{}.bin", prefix, chip);
+        std::fs::write(&path, image)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
@@ -1,9 +1,69 @@
+use crate::debugger::{Access, BreakReason, BusContention, Condition, Watchpoint};
+use crate::microcode::{MicrocodeRom, STEPS};
+use crate::stats::{Stats, StatsMark};
+use crate::vcd::{MAX_TRACE_SAMPLES, TraceSample};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+// Number of clock ticks that can be stepped back
+pub const HISTORY_LENGTH: usize = 4096;
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ClockMode {
     RUN,
     STEP,
 }
+// What happens when an instruction reaches the end of T7 without asserting PR,
+// as unprogrammed opcodes such as 0xF4 do
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IllegalOpcodePolicy {
+    // The T counter wraps around to the next fetch, like the 3-bit counter on the board
+    #[default]
+    Nop,
+    // Stop the CPU as if HLT was asserted
+    Halt,
+    // Wrap around like Nop, but stop the clock so the debugger can take a look
+    Trap,
+}
+
+impl IllegalOpcodePolicy {
+    pub const ALL: [IllegalOpcodePolicy; 3] = [
+        IllegalOpcodePolicy::Nop,
+        IllegalOpcodePolicy::Halt,
+        IllegalOpcodePolicy::Trap,
+    ];
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "nop" => Ok(IllegalOpcodePolicy::Nop),
+            "halt" => Ok(IllegalOpcodePolicy::Halt),
+            "trap" => Ok(IllegalOpcodePolicy::Trap),
+            _ => Err(format!(
+                "unknown illegal opcode policy '{}', expected nop, halt or trap",
+                text
+            )),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IllegalOpcodePolicy::Nop => "NOP",
+            IllegalOpcodePolicy::Halt => "Halt",
+            IllegalOpcodePolicy::Trap => "Trap",
+        }
+    }
+}
+
+// Behaviour switches set from the command line, kept when a new program is loaded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Settings {
+    // Stop the clock on bus contention instead of only recording it
+    pub halt_on_contention: bool,
+    pub illegal_opcode_policy: IllegalOpcodePolicy,
+}
+
 #[allow(non_snake_case)]
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct ControlWord {
     pub HLT: bool,
     pub MI: bool,
@@ -21,20 +81,142 @@ pub struct ControlWord {
     pub CO: bool,
     pub J: bool,
     pub FLG: bool,
+    pub FI: bool,
+    pub ONE: bool,
 }
 impl ControlWord {
-    pub fn to_array(&self) -> [bool; 16] {
+    // Number of control signals
+    pub const SIGNALS: usize = 18;
+
+    pub fn to_array(self) -> [bool; Self::SIGNALS] {
         [
             self.HLT, self.MI, self.RI, self.RO, self.II, self.PR, self.AI, self.AO, self.EO,
-            self.SU, self.BI, self.OI, self.CE, self.CO, self.J, self.FLG,
+            self.SU, self.BI, self.OI, self.CE, self.CO, self.J, self.FLG, self.FI, self.ONE,
         ]
     }
-    pub fn signal_names() -> [&'static str; 16] {
+    pub fn signal_names() -> [&'static str; Self::SIGNALS] {
         [
             "HLT", "MI", "RI", "RO", "II", "PR", "AI", "AO", "EO", "SU", "BI", "OI", "CE", "CO",
-            "J", "FLG",
+            "J", "FLG", "FI", "ONE",
         ]
     }
+    pub fn from_array(signals: [bool; Self::SIGNALS]) -> Self {
+        ControlWord {
+            HLT: signals[0],
+            MI: signals[1],
+            RI: signals[2],
+            RO: signals[3],
+            II: signals[4],
+            PR: signals[5],
+            AI: signals[6],
+            AO: signals[7],
+            EO: signals[8],
+            SU: signals[9],
+            BI: signals[10],
+            OI: signals[11],
+            CE: signals[12],
+            CO: signals[13],
+            J: signals[14],
+            FLG: signals[15],
+            FI: signals[16],
+            ONE: signals[17],
+        }
+    }
+    // Parse a space separated list of signal names, e.g. "RO AI CE"
+    pub fn from_signals(text: &str) -> Result<Self, String> {
+        let names = Self::signal_names();
+        let mut signals = [false; Self::SIGNALS];
+        for token in text.split_whitespace() {
+            match names
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(token))
+            {
+                Some(i) => signals[i] = true,
+                None => return Err(format!("unknown control signal '{}'", token)),
+            }
+        }
+        Ok(Self::from_array(signals))
+    }
+    // Pack the signals into EEPROM data lines, HLT in the top bit
+    pub fn to_bits(self) -> u32 {
+        self.to_array()
+            .iter()
+            .fold(0, |bits, &signal| (bits << 1) | signal as u32)
+    }
+    // Asserted signals that put a value on the bus
+    pub fn bus_drivers(self) -> Vec<&'static str> {
+        [
+            ("CO", self.CO),
+            ("RO", self.RO),
+            ("AO", self.AO),
+            ("EO", self.EO),
+        ]
+        .into_iter()
+        .filter(|&(_, asserted)| asserted)
+        .map(|(name, _)| name)
+        .collect()
+    }
+    // The asserted signals as a space separated list, the inverse of `from_signals`
+    pub fn to_signals(self) -> String {
+        let names = Self::signal_names();
+        let asserted: Vec<&str> = self
+            .to_array()
+            .iter()
+            .zip(names.iter())
+            .filter(|&(&bit, _)| bit)
+            .map(|(_, &name)| name)
+            .collect();
+        asserted.join(" ")
+    }
+}
+
+// Why `Sap1::run` returned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HaltReason {
+    // HLT was asserted
+    Halted,
+    // A breakpoint, watchpoint, condition, illegal opcode or bus contention
+    // stopped the clock
+    Break(BreakReason),
+    // The cycle budget ran out first
+    CycleLimit,
+}
+
+impl std::fmt::Display for HaltReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HaltReason::Halted => write!(f, "halted"),
+            HaltReason::Break(reason) => write!(f, "stopped: {}", reason),
+            HaltReason::CycleLimit => write!(f, "cycle limit reached"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunResult {
+    pub reason: HaltReason,
+    // Clock ticks and instructions executed by this run
+    pub cycles: u64,
+    pub instructions: u64,
+}
+
+// Everything a clock tick can change, recorded so ticks can be undone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineState {
+    pub reg_a: u8,
+    pub reg_b: u8,
+    pub alu_out: u8,
+    pub output: u8,
+    pub pc: u8,
+    pub memory: [u8; 256],
+    pub cf: bool,
+    pub zf: bool,
+    pub hlt: bool,
+    pub t_step: u8,
+    pub bus: u8,
+    pub mar: u8,
+    pub ir: u8,
+    pub control_word: ControlWord,
 }
 
 pub struct Sap1 {
@@ -52,7 +234,7 @@ pub struct Sap1 {
     // Memory (256 bytes)
     pub memory: [u8; 256],
 
-    // Flags: Carry and Zero
+    // Flags register: Carry and Zero, latched from the ALU by FI
     pub cf: bool,
     pub zf: bool,
 
@@ -74,6 +256,46 @@ pub struct Sap1 {
 
     // Control Word
     pub control_word: ControlWord,
+
+    // Control logic ROM
+    pub microcode: MicrocodeRom,
+
+    // Addresses to stop at when PC reaches them at T0
+    pub breakpoints: BTreeSet<u8>,
+    // RAM addresses to stop at when RO reads or RI writes them. Loading an
+    // opcode into IR is a fetch, not a read.
+    pub watchpoints: BTreeMap<u8, Watchpoint>,
+    // Conditions on registers and flags that stop the clock when they become true
+    pub conditions: Vec<Condition>,
+    // Set by the clock tick that hit a breakpoint, watchpoint or condition
+    pub break_reason: Option<BreakReason>,
+
+    // Number of ticks with more than one bus driver, and the latest of them
+    pub contention_count: u64,
+    pub last_contention: Option<BusContention>,
+
+    pub settings: Settings,
+
+    // Tick and instruction counters
+    pub stats: Stats,
+
+    // Signals after every tick while a waveform is being recorded
+    pub trace: Option<Vec<TraceSample>>,
+
+    // State before each of the most recent clock ticks and memory edits, oldest first
+    history: VecDeque<HistoryEntry>,
+}
+
+// One step that can be undone: the machine before a clock tick or a memory
+// edit, along with the debugger state the tick may change
+struct HistoryEntry {
+    state: MachineState,
+    break_reason: Option<BreakReason>,
+    contention_count: u64,
+    last_contention: Option<BusContention>,
+    stats: StatsMark,
+    // Whether the tick appended a sample to the waveform recording
+    traced: bool,
 }
 
 impl Sap1 {
@@ -94,421 +316,350 @@ impl Sap1 {
             ir: 0,
             clock_mode: ClockMode::STEP,
             control_word: ControlWord::default(),
+            microcode: MicrocodeRom::default(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            conditions: Vec::new(),
+            break_reason: None,
+            contention_count: 0,
+            last_contention: None,
+            settings: Settings::default(),
+            stats: Stats::default(),
+            trace: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    // Return to the power-on state without touching RAM or the microcode
+    pub fn reset(&mut self) {
+        self.reg_a = 0;
+        self.reg_b = 0;
+        self.alu_out = 0;
+        self.output = 0;
+        self.pc = 0;
+        self.cf = false;
+        self.zf = true;
+        self.hlt = false;
+        self.t_step = 0;
+        self.bus = 0;
+        self.mar = 0;
+        self.ir = 0;
+        self.control_word = ControlWord::default();
+        self.break_reason = None;
+        self.contention_count = 0;
+        self.last_contention = None;
+        self.stats = Stats::default();
+        self.history.clear();
+        self.sync_conditions();
+    }
+
+    pub fn state(&self) -> MachineState {
+        MachineState {
+            reg_a: self.reg_a,
+            reg_b: self.reg_b,
+            alu_out: self.alu_out,
+            output: self.output,
+            pc: self.pc,
+            memory: self.memory,
+            cf: self.cf,
+            zf: self.zf,
+            hlt: self.hlt,
+            t_step: self.t_step,
+            bus: self.bus,
+            mar: self.mar,
+            ir: self.ir,
+            control_word: self.control_word,
+        }
+    }
+
+    pub fn restore(&mut self, state: &MachineState) {
+        self.reg_a = state.reg_a;
+        self.reg_b = state.reg_b;
+        self.alu_out = state.alu_out;
+        self.output = state.output;
+        self.pc = state.pc;
+        self.memory = state.memory;
+        self.cf = state.cf;
+        self.zf = state.zf;
+        self.hlt = state.hlt;
+        self.t_step = state.t_step;
+        self.bus = state.bus;
+        self.mar = state.mar;
+        self.ir = state.ir;
+        self.control_word = state.control_word;
+        self.break_reason = None;
+        self.sync_conditions();
+    }
+
+    // Conditions only trigger on a change made by a clock tick, so they take
+    // the current state as their previous value when it is replaced otherwise
+    fn sync_conditions(&mut self) {
+        let mut conditions = std::mem::take(&mut self.conditions);
+        for condition in &mut conditions {
+            condition.sync(self);
         }
+        self.conditions = conditions;
     }
 
-    pub fn load_program(&mut self, program: &[u8]) {
-        for (i, &byte) in program.iter().enumerate() {
-            self.memory[i] = byte;
+    pub fn load_program(&mut self, program: &[u8]) -> Result<(), String> {
+        if program.len() > self.memory.len() {
+            return Err(format!(
+                "program is {} bytes but memory only holds {}",
+                program.len(),
+                self.memory.len()
+            ));
         }
+        self.memory[..program.len()].copy_from_slice(program);
+        Ok(())
+    }
+    // Write bytes into RAM from outside the program, e.g. the memory editor.
+    // The edit is recorded so stepping back undoes it instead of losing it.
+    pub fn write_memory(&mut self, address: usize, bytes: &[u8]) {
+        self.push_history();
+        self.memory[address..address + bytes.len()].copy_from_slice(bytes);
     }
+
+    fn push_history(&mut self) {
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(HistoryEntry {
+            state: self.state(),
+            break_reason: self.break_reason.clone(),
+            contention_count: self.contention_count,
+            last_contention: self.last_contention.clone(),
+            stats: self.stats.mark(),
+            traced: false,
+        });
+    }
+
     pub fn clock_tick(&mut self) {
+        self.push_history();
+
+        let was_halted = self.hlt;
         let control = self.get_control_word(self.ir, self.t_step);
-        self.control_word = control.clone();
+        let drivers = control.bus_drivers();
+        let contention = (drivers.len() > 1).then_some(BusContention {
+            opcode: self.ir,
+            t_step: self.t_step,
+            drivers,
+        });
+        self.control_word = control;
         self.t_step += 1;
+        self.break_reason = None;
         self.execute_control_word(&control);
+
+        if let Some(contention) = contention {
+            self.contention_count += 1;
+            if self.settings.halt_on_contention {
+                self.break_reason = Some(BreakReason::BusContention(contention.clone()));
+            }
+            self.last_contention = Some(contention);
+        }
+
+        // The T counter is only 3 bits wide, so it wraps after T7 whether or not
+        // the instruction asserted PR. Not getting there is an illegal instruction.
+        if self.t_step as usize >= STEPS {
+            self.t_step = 0;
+            if !self.hlt {
+                let illegal = BreakReason::IllegalOpcode(self.ir);
+                match self.settings.illegal_opcode_policy {
+                    IllegalOpcodePolicy::Nop => {}
+                    IllegalOpcodePolicy::Halt => {
+                        self.hlt = true;
+                        self.break_reason = Some(illegal);
+                    }
+                    IllegalOpcodePolicy::Trap => self.break_reason = Some(illegal),
+                }
+            }
+        }
+        self.stats
+            .record_tick(self.ir, self.t_step == 0 || (self.hlt && !was_halted));
+
+        if self.break_reason.is_none() && self.t_step == 0 && self.breakpoints.contains(&self.pc) {
+            self.break_reason = Some(BreakReason::Breakpoint(self.pc));
+        }
+
+        // Every condition is updated so each one tracks its own edge
+        let mut conditions = std::mem::take(&mut self.conditions);
+        for condition in &mut conditions {
+            if condition.update(self) && self.break_reason.is_none() {
+                self.break_reason = Some(BreakReason::Condition(condition.text.clone()));
+            }
+        }
+        self.conditions = conditions;
+
+        if self
+            .trace
+            .as_ref()
+            .is_some_and(|trace| trace.len() < MAX_TRACE_SAMPLES)
+        {
+            let sample = TraceSample::capture(self);
+            self.trace.as_mut().unwrap().push(sample);
+            if let Some(entry) = self.history.back_mut() {
+                entry.traced = true;
+            }
+        }
     }
 
-    fn get_control_word(&self, opcode: u8, t_step: u8) -> ControlWord {
-        match (opcode >> 4, t_step) {
-            (_, 0) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (_, 1) => ControlWord {
-                RO: true,
-                II: true,
-                CE: true,
-                ..Default::default()
-            },
-            (0x0, 2) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0x1, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0x1, 3) => ControlWord {
-                RO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0x1, 4) => ControlWord {
-                RO: true,
-                AI: true,
-                CE: true,
-                ..Default::default()
-            },
-            (0x1, 5) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0x2, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0x2, 3) => ControlWord {
-                RO: true,
-                AI: true,
-                CE: true,
-                ..Default::default()
-            },
-            (0x2, 4) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0x3, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0x3, 3) => ControlWord {
-                RO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0x3, 4) => ControlWord {
-                RO: true,
-                BI: true,
-                CE: true,
-                ..Default::default()
-            },
-            (0x3, 5) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0x4, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0x4, 3) => ControlWord {
-                RO: true,
-                BI: true,
-                CE: true,
-                ..Default::default()
-            },
-            (0x4, 4) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0x5, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0x5, 3) => ControlWord {
-                RO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0x5, 4) => ControlWord {
-                RO: true,
-                BI: true,
-                ..Default::default()
-            },
-            (0x5, 5) => ControlWord {
-                EO: true,
-                AI: true,
-                CE: true,
-                ..Default::default()
-            },
-            (0x5, 6) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0x6, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0x6, 3) => ControlWord {
-                RO: true,
-                BI: true,
-                ..Default::default()
-            },
-            (0x6, 4) => ControlWord {
-                EO: true,
-                AI: true,
-                CE: true,
-                ..Default::default()
-            },
-            (0x6, 5) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0x7, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0x7, 3) => ControlWord {
-                RO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0x7, 4) => ControlWord {
-                RO: true,
-                BI: true,
-                SU: true,
-                ..Default::default()
-            },
-            (0x7, 5) => ControlWord {
-                EO: true,
-                AI: true,
-                SU: true,
-                CE: true,
-                ..Default::default()
-            },
-            (0x7, 6) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0x8, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0x8, 3) => ControlWord {
-                RO: true,
-                BI: true,
-                SU: true,
-                ..Default::default()
-            },
-            (0x8, 4) => ControlWord {
-                EO: true,
-                AI: true,
-                SU: true,
-                CE: true,
-                ..Default::default()
-            },
-            (0x8, 5) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0x9, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0x9, 3) => ControlWord {
-                RI: true,
-                MI: true,
-                CE: true,
-                ..Default::default()
-            },
-            (0x9, 4) => ControlWord {
-                AO: true,
-                RI: true,
-                ..Default::default()
-            },
-            (0x9, 5) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0xA, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0xA, 3) => ControlWord {
-                RO: true,
-                J: true,
-                ..Default::default()
-            },
-            (0xA, 4) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0xB, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0xB, 3) => ControlWord {
-                RO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0xB, 4) => ControlWord {
-                RO: true,
-                BI: true,
-                SU: true,
-                ..Default::default()
-            },
-            (0xB, 5) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0xC, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0xC, 3) => ControlWord {
-                RO: true,
-                BI: true,
-                SU: true,
-                ..Default::default()
-            },
-            (0xC, 4) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0xD, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0xD, 3) => ControlWord {
-                FLG: !self.zf,
-                RO: true,
-                J: true,
-                ..Default::default()
-            },
-            (0xD, 4) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0xE, 2) => ControlWord {
-                CO: true,
-                MI: true,
-                ..Default::default()
-            },
-            (0xE, 3) => ControlWord {
-                FLG: self.zf,
-                RO: true,
-                J: true,
-                ..Default::default()
-            },
-            (0xE, 4) => ControlWord {
-                PR: true,
-                ..Default::default()
-            },
-            (0xF, _) => match (opcode & 0x0F, t_step) {
-                (0x0, 2) => ControlWord {
-                    CO: true,
-                    MI: true,
-                    ..Default::default()
-                },
-                (0x0, 3) => ControlWord {
-                    FLG: self.cf,
-                    RO: true,
-                    J: true,
-                    ..Default::default()
-                },
-                (0x0, 4) => ControlWord {
-                    PR: true,
-                    ..Default::default()
-                },
-                (0x1, 2) => ControlWord {
-                    CO: true,
-                    MI: true,
-                    ..Default::default()
-                },
-                (0x1, 3) => ControlWord {
-                    RO: true,
-                    MI: true,
-                    CE: true,
-                    ..Default::default()
-                },
-                (0x1, 4) => ControlWord {
-                    RO: true,
-                    AI: true,
-                    // reg_b = 1
-                    ..Default::default()
-                },
-                (0x1, 5) => ControlWord {
-                    EO: true,
-                    AI: true,
-                    ..Default::default()
-                },
-                (0x1, 6) => ControlWord {
-                    RI: true,
-                    AO: true,
-                    ..Default::default()
-                },
-                (0x1, 7) => ControlWord {
-                    PR: true,
-                    ..Default::default()
-                },
-                (0x2, 2) => ControlWord {
-                    CO: true,
-                    MI: true,
-                    ..Default::default()
-                },
-                (0x2, 3) => ControlWord {
-                    RO: true,
-                    MI: true,
-                    CE: true,
-                    ..Default::default()
-                },
-                (0x2, 4) => ControlWord {
-                    RO: true,
-                    AI: true,
-                    // reg_b = 1
-                    ..Default::default()
-                },
-                (0x2, 5) => ControlWord {
-                    EO: true,
-                    AI: true,
-                    SU: true,
-                    ..Default::default()
-                },
-                (0x2, 6) => ControlWord {
-                    RI: true,
-                    AO: true,
-                    ..Default::default()
-                },
-                (0x2, 7) => ControlWord {
-                    PR: true,
-                    ..Default::default()
-                },
-                (0x3, 2) => ControlWord {
-                    AO: true,
-                    OI: true,
-                    ..Default::default()
-                },
-                (0x3, 3) => ControlWord {
-                    PR: true,
-                    ..Default::default()
-                },
-                (0xF, 2) => ControlWord {
-                    HLT: true,
-                    ..Default::default()
-                },
-                (0xF, 3) => ControlWord {
-                    PR: true,
-                    ..Default::default()
-                },
-                _ => ControlWord::default(),
-            },
-
-            _ => ControlWord::default(),
+    // Start a new waveform recording from the current state
+    pub fn start_trace(&mut self) {
+        self.trace = Some(vec![TraceSample::capture(self)]);
+        // Ticks from before the recording have no samples to take back
+        for entry in &mut self.history {
+            entry.traced = false;
+        }
+    }
+
+    pub fn toggle_breakpoint(&mut self, address: u8) {
+        if !self.breakpoints.remove(&address) {
+            self.breakpoints.insert(address);
+        }
+    }
+
+    // Watch an address, or stop watching it when it is already watched the same way
+    pub fn toggle_watchpoint(&mut self, address: u8, watchpoint: Watchpoint) {
+        if self.watchpoints.get(&address) == Some(&watchpoint) {
+            self.watchpoints.remove(&address);
+        } else {
+            self.watchpoints.insert(address, watchpoint);
+        }
+    }
+
+    pub fn set_watchpoint(&mut self, address: u8, watchpoint: Watchpoint) {
+        if watchpoint == Watchpoint::default() {
+            self.watchpoints.remove(&address);
+        } else {
+            self.watchpoints.insert(address, watchpoint);
         }
     }
+
+    // A condition that already holds when added waits for it to become false
+    // and true again, like one that became true by a write from the debugger
+    pub fn add_condition(&mut self, mut condition: Condition) {
+        condition.sync(self);
+        self.conditions.push(condition);
+    }
+
+    fn check_watchpoint(&mut self, access: Access, value: u8) {
+        let address = self.mar;
+        if self
+            .watchpoints
+            .get(&address)
+            .is_some_and(|watchpoint| watchpoint.matches(access))
+        {
+            self.break_reason = Some(BreakReason::Watchpoint {
+                address,
+                access,
+                value,
+            });
+        }
+    }
+
+    // Run clock ticks until the T counter returns to T0, the CPU halts or a
+    // breakpoint is hit
+    pub fn step_instruction(&mut self) {
+        for _ in 0..STEPS {
+            if self.hlt {
+                break;
+            }
+            self.clock_tick();
+            if self.t_step == 0 || self.break_reason.is_some() {
+                break;
+            }
+        }
+    }
+
+    // Run until the CPU halts, the debugger stops the clock or `max_cycles`
+    // ticks have passed. An instruction counts once it returns to T0 or halts.
+    pub fn run(&mut self, max_cycles: u64) -> RunResult {
+        let start_ticks = self.stats.ticks;
+        let start_instructions = self.stats.instructions;
+        let reason = loop {
+            if self.hlt {
+                break HaltReason::Halted;
+            }
+            if self.stats.ticks - start_ticks == max_cycles {
+                break HaltReason::CycleLimit;
+            }
+            self.clock_tick();
+            if let Some(reason) = &self.break_reason {
+                break HaltReason::Break(reason.clone());
+            }
+        };
+        RunResult {
+            reason,
+            cycles: self.stats.ticks - start_ticks,
+            instructions: self.stats.instructions - start_instructions,
+        }
+    }
+
+    // Start counting from zero. Stepping back past this point leaves the
+    // counters at zero.
+    pub fn clear_stats(&mut self) {
+        self.stats = Stats::default();
+        for entry in &mut self.history {
+            entry.stats = StatsMark::default();
+        }
+    }
+
+    pub fn can_step_back(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    // Undo the last clock tick or memory edit. Returns false when there is no
+    // history left.
+    pub fn step_back(&mut self) -> bool {
+        match self.history.pop_back() {
+            Some(entry) => {
+                self.stats.rewind(entry.stats, self.ir);
+                self.restore(&entry.state);
+                self.break_reason = entry.break_reason;
+                self.contention_count = entry.contention_count;
+                self.last_contention = entry.last_contention;
+                if entry.traced
+                    && let Some(trace) = &mut self.trace
+                {
+                    trace.pop();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Undo ticks back to the start of the current instruction, or of the
+    // previous one when already at T0
+    pub fn step_back_instruction(&mut self) {
+        while self.step_back() {
+            if self.t_step == 0 {
+                break;
+            }
+        }
+    }
+
+    fn get_control_word(&self, opcode: u8, t_step: u8) -> ControlWord {
+        self.microcode.get(opcode, t_step, self.cf, self.zf)
+    }
     fn execute_control_word(&mut self, control: &ControlWord) {
-        self.alu_out = if control.SU {
-            let (result, borrow) = self.reg_a.overflowing_sub(self.reg_b);
-            self.cf = borrow;
-            self.zf = result == 0;
-            result
+        // ONE feeds the constant 1 into the ALU in place of the B register
+        let operand = if control.ONE { 1 } else { self.reg_b };
+        let (result, carry) = if control.SU {
+            self.reg_a.overflowing_sub(operand)
         } else {
-            let (result, carry) = self.reg_a.overflowing_add(self.reg_b);
-            self.cf = carry;
-            self.zf = result == 0;
-            result
+            self.reg_a.overflowing_add(operand)
         };
+        self.alu_out = result;
 
         if control.CO {
             self.bus = self.pc;
         }
         if control.RO {
             self.bus = self.memory[self.mar as usize];
+            // Instruction fetches would stop on every pass over watched code
+            if !control.II {
+                self.check_watchpoint(Access::Read, self.bus);
+            }
         }
         if control.AO {
             self.bus = self.reg_a;
@@ -522,6 +673,7 @@ impl Sap1 {
         }
         if control.RI {
             self.memory[self.mar as usize] = self.bus;
+            self.check_watchpoint(Access::Write, self.bus);
         }
         if control.II {
             self.ir = self.bus;
@@ -535,6 +687,10 @@ impl Sap1 {
         if control.OI {
             self.output = self.bus;
         }
+        if control.FI {
+            self.cf = carry;
+            self.zf = result == 0;
+        }
 
         if control.CE {
             self.pc = self.pc.wrapping_add(1);
@@ -552,11 +708,166 @@ impl Sap1 {
         if control.HLT {
             self.hlt = true;
         }
+    }
+}
 
-        if self.alu_out == 0 {
-            self.zf = true;
-        } else {
-            self.zf = false;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    fn load(source: &str) -> Sap1 {
+        let mut sap1 = Sap1::new();
+        let program = assembler::assemble(source).unwrap();
+        sap1.load_program(&program).unwrap();
+        sap1
+    }
+
+    // Everything stepping back has to put back the way it was
+    #[derive(Debug, PartialEq)]
+    struct Checkpoint {
+        state: MachineState,
+        stats: StatsMark,
+        opcode_count: [u64; 256],
+        opcode_ticks: [u64; 256],
+        break_reason: Option<BreakReason>,
+        contention_count: u64,
+        last_contention: Option<BusContention>,
+        trace_len: usize,
+    }
+
+    fn checkpoint(sap1: &Sap1) -> Checkpoint {
+        Checkpoint {
+            state: sap1.state(),
+            stats: sap1.stats.mark(),
+            opcode_count: sap1.stats.opcode_count,
+            opcode_ticks: sap1.stats.opcode_ticks,
+            break_reason: sap1.break_reason.clone(),
+            contention_count: sap1.contention_count,
+            last_contention: sap1.last_contention.clone(),
+            trace_len: sap1.trace.as_ref().map_or(0, Vec::len),
         }
     }
+
+    #[test]
+    fn step_back_undoes_ticks_and_memory_edits() {
+        let source = "
+        LDA # 3
+        .byte 0xF4      ; drives the bus from A and the ALU at once
+        ADD $ value
+        OUT
+        HLT
+value:  .byte 4
+";
+        let mut sap1 = load(source);
+        let mut text = MicrocodeRom::default().to_text();
+        text.push_str("CLASH 0xF4: 2: AO EO; 3: PR\n");
+        sap1.microcode = MicrocodeRom::from_text(&text).unwrap();
+        sap1.settings.halt_on_contention = true;
+        sap1.start_trace();
+
+        let mut checkpoints = Vec::new();
+        for _ in 0..3 {
+            checkpoints.push(checkpoint(&sap1));
+            sap1.clock_tick();
+        }
+        checkpoints.push(checkpoint(&sap1));
+        sap1.write_memory(7, &[5]);
+        while !sap1.hlt {
+            checkpoints.push(checkpoint(&sap1));
+            sap1.clock_tick();
+        }
+        assert_eq!(sap1.output, 8);
+        assert_eq!(sap1.contention_count, 1);
+        assert_eq!(sap1.stats.instructions, 5);
+
+        while let Some(expected) = checkpoints.pop() {
+            assert!(sap1.step_back());
+            assert_eq!(checkpoint(&sap1), expected);
+        }
+        assert!(!sap1.step_back());
+        assert_eq!(sap1.memory[7], 4);
+    }
+
+    #[test]
+    fn stats_cleared_mid_instruction_rewind_to_zero() {
+        let mut sap1 = load("LDA # 3\nADD # 4\nHLT");
+        let start = sap1.state();
+        sap1.clock_tick();
+        sap1.clock_tick();
+        sap1.clear_stats();
+        let cleared = checkpoint(&sap1);
+
+        sap1.run(100);
+        // The LDA only counts the ticks after the counters were cleared
+        assert_eq!(sap1.stats.instructions, 3);
+        assert_eq!(sap1.stats.opcode_ticks[0x20], 3);
+        assert_eq!(sap1.stats.opcode_ticks[0x60], 6);
+
+        while sap1.stats.ticks > 0 {
+            assert!(sap1.step_back());
+        }
+        assert_eq!(checkpoint(&sap1), cleared);
+        assert!(sap1.step_back() && sap1.step_back());
+        assert_eq!(sap1.state(), start);
+        assert_eq!(sap1.stats.mark(), StatsMark::default());
+        assert_eq!(sap1.stats.opcode_count, [0; 256]);
+    }
+
+    #[test]
+    fn flags_only_change_on_fi() {
+        let mut sap1 = load("LDA # 255\nADD # 1\nLDA # 5\nSTA 0x20\nHLT");
+        sap1.run(100);
+        // ADD latched the flags, the LDA and STA after it left them alone
+        assert_eq!((sap1.reg_a, sap1.memory[0x20]), (5, 5));
+        assert!(sap1.cf && sap1.zf);
+    }
+
+    #[test]
+    fn inc_and_dec_wrap_memory() {
+        let source = "
+        INC max
+        DEC zero
+        DEC one
+        HLT
+max:    .byte 255
+zero:   .byte 0
+one:    .byte 1
+";
+        let mut sap1 = load(source);
+        let flags = |sap1: &Sap1| (sap1.cf, sap1.zf);
+        sap1.step_instruction();
+        assert_eq!((sap1.memory[7], flags(&sap1)), (0, (true, true)));
+        sap1.step_instruction();
+        assert_eq!((sap1.memory[8], flags(&sap1)), (255, (true, false)));
+        sap1.step_instruction();
+        assert_eq!((sap1.memory[9], flags(&sap1)), (0, (false, true)));
+        assert_eq!(sap1.run(100).reason, HaltReason::Halted);
+    }
+
+    #[test]
+    fn illegal_opcode_policies() {
+        let source = "LDA # 7\n.byte 0xF4\nOUT\nHLT";
+        let illegal = HaltReason::Break(BreakReason::IllegalOpcode(0xF4));
+
+        // 0xF4 runs to the end of T7 and the counter wraps to the next fetch
+        let mut sap1 = load(source);
+        assert_eq!(sap1.run(100).reason, HaltReason::Halted);
+        assert_eq!(sap1.output, 7);
+        assert_eq!(sap1.stats.opcode_ticks[0xF4], 8);
+
+        let mut sap1 = load(source);
+        sap1.settings.illegal_opcode_policy = IllegalOpcodePolicy::Halt;
+        assert_eq!(sap1.run(100).reason, illegal);
+        assert!(sap1.hlt);
+        assert_eq!(sap1.output, 0);
+
+        let mut sap1 = load(source);
+        sap1.settings.illegal_opcode_policy = IllegalOpcodePolicy::Trap;
+        assert_eq!(sap1.run(100).reason, illegal);
+        assert!(!sap1.hlt);
+        assert_eq!((sap1.t_step, sap1.pc), (0, 3));
+        assert_eq!(sap1.run(100).reason, HaltReason::Halted);
+        assert_eq!(sap1.output, 7);
+    }
 }
@@ -0,0 +1,115 @@
+// The R-SAP-1 instruction set as seen by the assembler and disassembler
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    // No operand byte, e.g. OUT
+    None,
+    // `$ addr`: operand is the address of the value in memory
+    Memory,
+    // `# value`: operand is the value itself
+    Immediate,
+    // Plain address, used by stores and jumps
+    Address,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Instruction {
+    pub mnemonic: &'static str,
+    pub operand: Operand,
+    pub opcode: u8,
+}
+
+impl Instruction {
+    // Number of bytes the instruction occupies in memory
+    pub fn size(&self) -> usize {
+        match self.operand {
+            Operand::None => 1,
+            _ => 2,
+        }
+    }
+
+    // Mnemonic with its addressing mode marker, e.g. "LDA #"
+    pub fn syntax(&self) -> String {
+        match self.operand {
+            Operand::Memory => format!("{} $", self.mnemonic),
+            Operand::Immediate => format!("{} #", self.mnemonic),
+            Operand::None | Operand::Address => self.mnemonic.to_string(),
+        }
+    }
+
+    // Name of the instruction in microcode tables: the mnemonic, with _MEM or
+    // _IMM appended for the addressing mode, e.g. "LDA_IMM"
+    pub fn table_name(&self) -> String {
+        match self.operand {
+            Operand::Memory => format!("{}_MEM", self.mnemonic),
+            Operand::Immediate => format!("{}_IMM", self.mnemonic),
+            Operand::None | Operand::Address => self.mnemonic.to_string(),
+        }
+    }
+
+    // Every opcode byte that decodes to this instruction
+    pub fn opcodes(&self) -> RangeInclusive<u8> {
+        if self.opcode < 0xF0 {
+            self.opcode..=self.opcode | 0x0F
+        } else {
+            self.opcode..=self.opcode
+        }
+    }
+
+    pub fn from_table_name(name: &str) -> Option<&'static Instruction> {
+        INSTRUCTIONS
+            .iter()
+            .find(|inst| inst.table_name().eq_ignore_ascii_case(name))
+    }
+
+    // Find the instruction an opcode byte executes. Below 0xF0 only the high
+    // nibble is decoded, the 0xF_ group is decoded on the full byte.
+    pub fn decode(byte: u8) -> Option<&'static Instruction> {
+        let opcode = if byte < 0xF0 { byte & 0xF0 } else { byte };
+        INSTRUCTIONS.iter().find(|inst| inst.opcode == opcode)
+    }
+
+    pub fn lookup(mnemonic: &str, operand: Operand) -> Option<&'static Instruction> {
+        INSTRUCTIONS
+            .iter()
+            .find(|inst| inst.mnemonic.eq_ignore_ascii_case(mnemonic) && inst.operand == operand)
+    }
+
+    pub fn is_mnemonic(mnemonic: &str) -> bool {
+        INSTRUCTIONS
+            .iter()
+            .any(|inst| inst.mnemonic.eq_ignore_ascii_case(mnemonic))
+    }
+}
+
+const fn inst(mnemonic: &'static str, operand: Operand, opcode: u8) -> Instruction {
+    Instruction {
+        mnemonic,
+        operand,
+        opcode,
+    }
+}
+
+pub const INSTRUCTIONS: [Instruction; 20] = [
+    inst("NOP", Operand::None, 0x00),
+    inst("LDA", Operand::Memory, 0x10),
+    inst("LDA", Operand::Immediate, 0x20),
+    inst("LDB", Operand::Memory, 0x30),
+    inst("LDB", Operand::Immediate, 0x40),
+    inst("ADD", Operand::Memory, 0x50),
+    inst("ADD", Operand::Immediate, 0x60),
+    inst("SUB", Operand::Memory, 0x70),
+    inst("SUB", Operand::Immediate, 0x80),
+    inst("STA", Operand::Address, 0x90),
+    inst("JMP", Operand::Address, 0xA0),
+    inst("CMP", Operand::Memory, 0xB0),
+    inst("CMP", Operand::Immediate, 0xC0),
+    inst("BNE", Operand::Address, 0xD0),
+    inst("JPZ", Operand::Address, 0xE0),
+    inst("JPC", Operand::Address, 0xF0),
+    inst("INC", Operand::Address, 0xF1),
+    inst("DEC", Operand::Address, 0xF2),
+    inst("OUT", Operand::None, 0xF3),
+    inst("HLT", Operand::None, 0xFF),
+];
@@ -0,0 +1,264 @@
+use crate::assembler;
+use std::path::Path;
+
+// Program file formats, picked from the file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramFormat {
+    // Raw memory image
+    Binary,
+    // Intel HEX records, or plain hex bytes separated by whitespace
+    Hex,
+    // Assembly source
+    Assembly,
+}
+
+impl ProgramFormat {
+    pub fn from_path(path: &str) -> Result<Self, String> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "bin" | "rom" => Ok(ProgramFormat::Binary),
+            "hex" | "ihx" => Ok(ProgramFormat::Hex),
+            "asm" | "s" => Ok(ProgramFormat::Assembly),
+            _ => Err(format!(
+                "{}: unknown program format, expected .bin, .hex or .asm",
+                path
+            )),
+        }
+    }
+}
+
+// Read a program file into the bytes to load at address 0
+pub fn load_program_file(path: &str) -> Result<Vec<u8>, String> {
+    let format = ProgramFormat::from_path(path)?;
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let program = match format {
+        ProgramFormat::Binary => bytes,
+        ProgramFormat::Hex => {
+            let text =
+                String::from_utf8(bytes).map_err(|_| format!("{}: not a text file", path))?;
+            parse_hex(&text).map_err(|e| format!("{}:{}", path, e))?
+        }
+        ProgramFormat::Assembly => {
+            let source =
+                String::from_utf8(bytes).map_err(|_| format!("{}: not a text file", path))?;
+            let memory = assembler::assemble(&source).map_err(|errors| {
+                let lines: Vec<String> = errors
+                    .iter()
+                    .map(|error| format!("{}:{}", path, error))
+                    .collect();
+                lines.join("\n")
+            })?;
+            memory.to_vec()
+        }
+    };
+    if program.len() > 256 {
+        return Err(format!(
+            "{}: program is {} bytes but memory only holds 256",
+            path,
+            program.len()
+        ));
+    }
+    Ok(program)
+}
+
+// Parse either Intel HEX (lines starting with ':') or plain hex bytes such
+// as "20 05 F3 FF". Errors are prefixed with the line number.
+fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let is_intel = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .is_some_and(|line| line.starts_with(':'));
+    if is_intel {
+        parse_intel_hex(text)
+    } else {
+        parse_plain_hex(text)
+    }
+}
+
+fn parse_plain_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut program = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split([';', '#']).next().unwrap_or("");
+        for word in line.split(|c: char| c.is_whitespace() || c == ',') {
+            if word.is_empty() {
+                continue;
+            }
+            let digits = word
+                .strip_prefix("0x")
+                .or_else(|| word.strip_prefix("0X"))
+                .unwrap_or(word);
+            let byte = u8::from_str_radix(digits, 16)
+                .ok()
+                .filter(|_| digits.len() <= 2)
+                .ok_or_else(|| format!("{}: '{}' is not a hex byte", i + 1, word))?;
+            program.push(byte);
+        }
+    }
+    Ok(program)
+}
+
+fn parse_intel_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut program = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let digits = line
+            .strip_prefix(':')
+            .ok_or_else(|| format!("{}: record does not start with ':'", line_number))?;
+        if digits.len() % 2 != 0 || digits.len() < 10 {
+            return Err(format!("{}: malformed record", line_number));
+        }
+        // The record is sliced two bytes at a time below
+        if !digits.is_ascii() {
+            return Err(format!("{}: invalid hex digits", line_number));
+        }
+        let record = (0..digits.len())
+            .step_by(2)
+            .map(|j| u8::from_str_radix(&digits[j..j + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| format!("{}: invalid hex digits", line_number))?;
+
+        let length = record[0] as usize;
+        if record.len() != length + 5 {
+            return Err(format!("{}: record length does not match", line_number));
+        }
+        if record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+            return Err(format!("{}: checksum mismatch", line_number));
+        }
+
+        let address = u16::from_be_bytes([record[1], record[2]]) as usize;
+        let data = &record[4..4 + length];
+        match record[3] {
+            0x00 => {
+                let end = address + data.len();
+                if end > 256 {
+                    return Err(format!(
+                        "{}: data at address {} is outside the 256 byte memory",
+                        line_number,
+                        end - 1
+                    ));
+                }
+                if program.len() < end {
+                    program.resize(end, 0);
+                }
+                program[address..end].copy_from_slice(data);
+            }
+            0x01 => break,
+            other => {
+                return Err(format!(
+                    "{}: unsupported record type {:02X}",
+                    line_number, other
+                ));
+            }
+        }
+    }
+    Ok(program)
+}
+
+// Write a memory image as raw bytes (.bin) or as hex text (.hex), 16 bytes per line
+pub fn save_memory_image(path: &str, memory: &[u8]) -> Result<(), String> {
+    let contents = match ProgramFormat::from_path(path)? {
+        ProgramFormat::Binary => memory.to_vec(),
+        ProgramFormat::Hex => {
+            let lines: Vec<String> = memory
+                .chunks(16)
+                .map(|row| {
+                    let bytes: Vec<String> =
+                        row.iter().map(|byte| format!("{:02X}", byte)).collect();
+                    bytes.join(" ")
+                })
+                .collect();
+            (lines.join("\n") + "\n").into_bytes()
+        }
+        ProgramFormat::Assembly => {
+            return Err(format!(
+                "{}: memory images can only be saved as .bin or .hex",
+                path
+            ));
+        }
+    };
+    std::fs::write(path, contents).map_err(|e| format!("{}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intel_hex_places_data_records_at_their_address() {
+        let program = parse_hex(":040000002005F3FFE5\n\n:01001000AA45\n:00000001FF\n").unwrap();
+        assert_eq!(program.len(), 0x11);
+        assert_eq!(program[..4], [0x20, 0x05, 0xF3, 0xFF]);
+        assert!(program[4..0x10].iter().all(|&byte| byte == 0));
+        assert_eq!(program[0x10], 0xAA);
+    }
+
+    #[test]
+    fn intel_hex_stops_at_the_end_of_file_record() {
+        let program = parse_hex(":00000001FF\n:01001000AA45\nnot a record\n").unwrap();
+        assert!(program.is_empty());
+    }
+
+    #[test]
+    fn intel_hex_rejects_bad_records() {
+        assert_eq!(
+            parse_hex(":040000002005F3FFE6"),
+            Err("1: checksum mismatch".to_string())
+        );
+        assert_eq!(
+            parse_hex(":040000002005F3E5"),
+            Err("1: record length does not match".to_string())
+        );
+        assert_eq!(
+            parse_hex(":04000000"),
+            Err("1: malformed record".to_string())
+        );
+        assert_eq!(
+            parse_hex(":04000000200éF3E"),
+            Err("1: invalid hex digits".to_string())
+        );
+        assert_eq!(
+            parse_hex(":01001000AA45\n20 05"),
+            Err("2: record does not start with ':'".to_string())
+        );
+        assert_eq!(
+            parse_hex(":020000021000EC"),
+            Err("1: unsupported record type 02".to_string())
+        );
+        assert_eq!(
+            parse_hex(":0200FF000102FC"),
+            Err("1: data at address 256 is outside the 256 byte memory".to_string())
+        );
+    }
+
+    #[test]
+    fn plain_hex_reads_bytes_and_skips_comments() {
+        assert_eq!(
+            parse_hex("20 05 ; LDA # 5\n0xF3,FF # OUT, HLT\n"),
+            Ok(vec![0x20, 0x05, 0xF3, 0xFF])
+        );
+        assert_eq!(
+            parse_hex("20\n123"),
+            Err("2: '123' is not a hex byte".to_string())
+        );
+    }
+
+    #[test]
+    fn format_comes_from_the_extension() {
+        assert_eq!(
+            ProgramFormat::from_path("demo.ASM"),
+            Ok(ProgramFormat::Assembly)
+        );
+        assert_eq!(ProgramFormat::from_path("a.ihx"), Ok(ProgramFormat::Hex));
+        assert_eq!(ProgramFormat::from_path("a.rom"), Ok(ProgramFormat::Binary));
+        assert!(ProgramFormat::from_path("notes.txt").is_err());
+    }
+}
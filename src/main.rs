@@ -1,146 +1,310 @@
+mod assembler;
+mod debugger;
+mod eeprom;
 mod emulator;
+mod isa;
+mod loader;
+mod microcode;
+mod snapshot;
+mod stats;
 mod ui;
-
+mod validator;
+mod vcd;
+
+use debugger::{Condition, Watchpoint};
+use eeprom::EepromLayout;
+use emulator::{ControlWord, HaltReason, IllegalOpcodePolicy, Sap1, Settings};
+use microcode::MicrocodeRom;
+use snapshot::Snapshot;
 use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() > 1 && args[1] == "--no-gui" {
-        terminal_mode();
-    } else {
-        ui::run();
+    let mut no_gui = false;
+    let mut microcode_path = None;
+    let mut dump_microcode_path = None;
+    let mut check_microcode = false;
+    let mut eeprom_prefix = None;
+    let mut eeprom_layout = EepromLayout::default();
+    let mut assemble_path = None;
+    let mut program_path = None;
+    let mut snapshot_path = None;
+    let mut vcd_path = None;
+    let mut run_cycles = None;
+    let mut show_stats = false;
+    let mut settings = Settings::default();
+
+    let mut args_iter = args.iter().skip(1);
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--no-gui" => no_gui = true,
+            "--halt-on-contention" => settings.halt_on_contention = true,
+            "--stats" => show_stats = true,
+            "--illegal-opcode" => {
+                let policy = expect_value(arg, args_iter.next());
+                settings.illegal_opcode_policy = IllegalOpcodePolicy::parse(&policy)
+                    .unwrap_or_else(|e| {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    });
+            }
+            "--microcode" => microcode_path = Some(expect_value(arg, args_iter.next())),
+            "--dump-microcode" => dump_microcode_path = Some(expect_value(arg, args_iter.next())),
+            "--check-microcode" => check_microcode = true,
+            "--assemble" => assemble_path = Some(expect_value(arg, args_iter.next())),
+            "--snapshot" => snapshot_path = Some(expect_value(arg, args_iter.next())),
+            "--vcd" => vcd_path = Some(expect_value(arg, args_iter.next())),
+            "--run" => {
+                let cycles = expect_value(arg, args_iter.next());
+                run_cycles = Some(cycles.parse::<u64>().unwrap_or_else(|_| {
+                    eprintln!("--run expects a number of cycles, got '{}'", cycles);
+                    process::exit(1);
+                }));
+            }
+            "--export-eeprom" => eeprom_prefix = Some(expect_value(arg, args_iter.next())),
+            "--eeprom-layout" => {
+                let layout = expect_value(arg, args_iter.next());
+                eeprom_layout = EepromLayout::parse(&layout).unwrap_or_else(|e| {
+                    eprintln!("Invalid EEPROM layout: {}", e);
+                    process::exit(1);
+                });
+            }
+            _ if arg.starts_with("--") || program_path.is_some() => {
+                eprintln!("Unknown argument: {}", arg);
+                eprintln!(
+                    "Usage: rsap1 [--no-gui | --run CYCLES [--stats]] [--vcd FILE] [--microcode FILE] [--snapshot FILE] [--halt-on-contention] [--illegal-opcode nop|halt|trap] [program.bin|.hex|.asm]"
+                );
+                eprintln!(
+                    "       rsap1 [--microcode FILE] --check-microcode | --dump-microcode FILE | --export-eeprom PREFIX [--eeprom-layout flags,step,opcode]"
+                );
+                eprintln!("       rsap1 --assemble program.asm");
+                process::exit(1);
+            }
+            _ => program_path = Some(arg.clone()),
+        }
     }
-}
-
-fn terminal_mode() {
-    use emulator::{ClockMode, Sap1};
-
-    let mut sap1 = Sap1::new();
-
-    let mut program = [0u8; 256];
-
-    // Test program that exercises most instructions
-
-    // Test 1: Store values in memory for later use
-    program[0] = 0b00100000; // LDA #
-    program[1] = 0b01100100; // Load 100 into A
-
-    program[2] = 0b10010000; // STA
-    program[3] = 0b11110000; // Store at address 240
-
-    program[4] = 0b00100000; // LDA #
-    program[5] = 0b00110010; // Load 50 into A
-
-    program[6] = 0b10010000; // STA
-    program[7] = 0b11110011; // Store at address 241
-
-    // Test 2: ADD $ (add from memory)
-    program[8] = 0b00100000; // LDA #
-    program[9] = 0b00001010; // Load 10 into A
-
-    program[10] = 0b01010000; // ADD $
-    program[11] = 0b11110000; // Add value from address 240 (100)
-
-    program[12] = 0b11110011; // OUT (should output 110)
-
-    // Test 3: SUB $ (subtract from memory)
-    program[13] = 0b01110000; // SUB $
-    program[14] = 0b11110001; // Subtract value from address 241 (50)
-
-    program[15] = 0b11110011; // OUT (should output 60)
-
-    // Test 4: LDB $ (load B from memory)
-    program[16] = 0b00110000; // LDB $
-    program[17] = 0b11110001; // Load B from address 241 (50)
-
-    program[18] = 0b01100000; // ADD #
-    program[19] = 0b00001010; // Add 10 to A (should be 70)
 
-    program[20] = 0b11110011; // OUT (should output 70, B should be 10 from last operation)
-
-    // Test 5: CMP $ (compare with memory)
-    program[21] = 0b00100000; // LDA #
-    program[22] = 0b01100100; // Load 100 into A
-
-    program[23] = 0b10110000; // CMP $
-    program[24] = 0b11110000; // Compare with address 240 (100)
-
-    program[25] = 0b11100000; // JPZ
-    program[26] = 0b00011100; // Jump to 28 if equal (should jump)
-
-    program[27] = 0b11110011; // OUT (should be skipped)
-
-    // Test 6: Test overflow with ADD $
-    program[28] = 0b00100000; // LDA #
-    program[29] = 0b11111111; // Load 255 into A
-
-    program[30] = 0b10010000; // STA
-    program[31] = 0b11110010; // Store at address 242
-
-    program[32] = 0b01010000; // ADD $
-    program[33] = 0b11110010; // Add from address 242 (255+255=254 with carry)
-
-    program[34] = 0b11110000; // JPC
-    program[35] = 0b00100101; // Jump to 37 if carry set
-
-    program[36] = 0b11110011; // OUT (should be skipped)
-
-    // Test 7: Test underflow with SUB $
-    program[37] = 0b00100000; // LDA #
-    program[38] = 0b00000101; // Load 5 into A
-
-    program[39] = 0b10010000; // STA
-    program[40] = 0b11110011; // Store at address 243
-
-    program[41] = 0b00100000; // LDA #
-    program[42] = 0b00000011; // Load 3 into A
-
-    program[43] = 0b01110000; // SUB $
-    program[44] = 0b11110011; // Subtract from address 243 (3-5 = underflow)
-
-    program[45] = 0b11110000; // JPC  
-    program[46] = 0b00110000; // Jump to 48 if borrow/carry
-
-    program[47] = 0b11110011; // OUT (should be skipped)
-
-    // Test 8: JMP (unconditional jump)
-    program[48] = 0b00100000; // LDA #
-    program[49] = 0b10101010; // Load 170 into A
-
-    program[50] = 0b10100000; // JMP
-    program[51] = 0b00110101; // Jump to address 53
+    let microcode = match &microcode_path {
+        Some(path) => MicrocodeRom::load(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load microcode: {}", e);
+            process::exit(1);
+        }),
+        None => MicrocodeRom::default(),
+    };
+
+    if let Some(path) = dump_microcode_path {
+        if let Err(e) = microcode.save(&path) {
+            eprintln!("Failed to write microcode to {}: {}", path, e);
+            process::exit(1);
+        }
+        println!("Microcode written to {}", path);
+        return;
+    }
 
-    program[52] = 0b11110011; // OUT (should be skipped)
+    if check_microcode {
+        // Notes are listed but don't fail the check
+        let issues = validator::validate(&microcode);
+        for issue in &issues {
+            if issue.is_note() {
+                println!("Note: {}", issue);
+            } else {
+                println!("{}", issue);
+            }
+        }
+        let problems = issues.iter().filter(|issue| !issue.is_note()).count();
+        if problems > 0 {
+            eprintln!("{} microcode problems found", problems);
+            process::exit(1);
+        }
+        println!("No microcode problems found");
+        return;
+    }
 
-    // Test 9: NOP (does nothing)
-    program[53] = 0b00000000; // NOP
-    program[54] = 0b00000000; // NOP
-    program[55] = 0b00000000; // NOP
+    if let Some(path) = assemble_path {
+        assemble_file(&path);
+        return;
+    }
 
-    program[56] = 0b11110011; // OUT (should output 170)
+    if let Some(prefix) = eeprom_prefix {
+        for issue in validator::validate(&microcode) {
+            if !issue.is_note() {
+                eprintln!("Warning: {}", issue);
+            }
+        }
+        match eeprom::export(&microcode, &eeprom_layout, &prefix) {
+            Ok(paths) => {
+                println!(
+                    "{} control signals need {} EEPROMs:",
+                    ControlWord::SIGNALS,
+                    eeprom::chip_count()
+                );
+                for (path, lines) in paths.iter().zip(eeprom::pinout()) {
+                    let pins: Vec<String> = lines
+                        .iter()
+                        .enumerate()
+                        .map(|(line, signal)| format!("D{} {}", 7 - line, signal.unwrap_or("-")))
+                        .collect();
+                    println!("  {}: {}", path, pins.join(", "));
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to write EEPROM images: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
 
-    // Test 10: Final test - multiple operations
-    program[57] = 0b00100000; // LDA #
-    program[58] = 0b00000000; // Load 0
+    let program = program_path.as_deref().map(|path| {
+        loader::load_program_file(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load program:\n{}", e);
+            process::exit(1);
+        })
+    });
+
+    let snapshot = snapshot_path.as_deref().map(|path| {
+        if program.is_some() {
+            eprintln!("A snapshot already holds memory, it can't be combined with a program");
+            process::exit(1);
+        }
+        Snapshot::load(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load snapshot: {}", e);
+            process::exit(1);
+        })
+    });
+
+    if let Some(max_cycles) = run_cycles {
+        run_headless(
+            build_machine(microcode, program, snapshot, settings),
+            vcd_path,
+            max_cycles,
+            show_stats,
+        );
+    } else if no_gui {
+        terminal_mode(
+            build_machine(microcode, program, snapshot, settings),
+            vcd_path,
+        );
+    } else {
+        if vcd_path.is_some() {
+            eprintln!("--vcd records terminal mode runs, use File > Save waveform in the GUI");
+            process::exit(1);
+        }
+        ui::run(microcode, microcode_path, program, snapshot, settings);
+    }
+}
 
-    program[59] = 0b11000000; // CMP #
-    program[60] = 0b00000000; // Compare with 0 (should set ZF)
+fn expect_value(flag: &str, value: Option<&String>) -> String {
+    match value {
+        Some(value) => value.clone(),
+        None => {
+            eprintln!("{} expects a value", flag);
+            process::exit(1);
+        }
+    }
+}
 
-    program[61] = 0b11010000; // BNE
-    program[62] = 0b01000000; // Jump to 64 if not equal (shouldn't jump)
+// Assemble `path` into a 256 byte memory image next to it with a .bin extension
+fn assemble_file(path: &str) {
+    let source = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", path, e);
+        process::exit(1);
+    });
+    let memory = assembler::assemble(&source).unwrap_or_else(|errors| {
+        for error in errors {
+            eprintln!("{}:{}", path, error);
+        }
+        process::exit(1);
+    });
+    let output = Path::new(path).with_extension("bin");
+    if let Err(e) = fs::write(&output, memory) {
+        eprintln!("Failed to write {}: {}", output.display(), e);
+        process::exit(1);
+    }
+    println!("Assembled {} to {}", path, output.display());
+}
 
-    program[63] = 0b11110011; // OUT (should output 0)
+// The machine for terminal and headless runs, with the built-in self test
+// loaded when neither a program nor a snapshot is given
+fn build_machine(
+    microcode: MicrocodeRom,
+    program: Option<Vec<u8>>,
+    snapshot: Option<Snapshot>,
+    settings: Settings,
+) -> Sap1 {
+    let mut sap1 = Sap1::new();
+    sap1.microcode = microcode;
+    sap1.settings = settings;
+
+    match (program, snapshot) {
+        (_, Some(snapshot)) => snapshot.apply(&mut sap1),
+        (Some(program), None) => sap1
+            .load_program(&program)
+            .expect("loader rejects oversized programs"),
+        (None, None) => {
+            let program = assembler::assemble(include_str!("../programs/selftest.asm"))
+                .expect("built-in test program assembles");
+            sap1.load_program(&program)
+                .expect("built-in test program fits in memory");
+
+            println!("Expected outputs: 110, 60, 70, 170, 0");
+            println!("Expected final: A=0, CF=false, ZF=true (from CMP # 0)");
+        }
+    }
+    sap1
+}
 
-    program[64] = 0b11111111; // HLT
+// Run without interaction for at most `max_cycles` ticks. Exits with status 2
+// unless the program halted, so scripts can tell a hang from a halt.
+fn run_headless(mut sap1: Sap1, vcd_path: Option<String>, max_cycles: u64, show_stats: bool) {
+    if vcd_path.is_some() {
+        sap1.start_trace();
+    }
+    let result = sap1.run(max_cycles);
+    println!(
+        "{} after {} cycles, {} instructions",
+        result.reason, result.cycles, result.instructions
+    );
+    println!(
+        "A={} B={} OUT={} PC={} CF={} ZF={}",
+        sap1.reg_a, sap1.reg_b, sap1.output, sap1.pc, sap1.cf, sap1.zf
+    );
+    if show_stats {
+        print!("{}", sap1.stats.report());
+    }
+    save_waveform(&sap1, vcd_path);
+    if result.reason != HaltReason::Halted {
+        process::exit(2);
+    }
+}
 
-    sap1.load_program(&program);
+fn terminal_mode(mut sap1: Sap1, vcd_path: Option<String>) {
+    use emulator::ClockMode;
 
-    println!("Expected outputs: 110, 60, 70, 170, 0");
-    println!("Expected final: A=0, CF=true (from underflow), ZF=true");
+    if vcd_path.is_some() {
+        sap1.start_trace();
+    }
     println!("\nPress Enter to start...");
 
+    let mut reported_contentions = 0;
     loop {
+        // Reset, loading and stepping back lower the count
+        if sap1.contention_count < reported_contentions {
+            reported_contentions = sap1.contention_count;
+        }
+        if sap1.contention_count > reported_contentions {
+            reported_contentions = sap1.contention_count;
+            if !sap1.settings.halt_on_contention
+                && let Some(contention) = &sap1.last_contention
+            {
+                println!("Warning: {}", contention);
+            }
+        }
+
         if sap1.hlt {
             println!("\n=== Program Halted! ===");
             println!("Final State:");
@@ -148,26 +312,155 @@ fn terminal_mode() {
             println!("B register: {}", sap1.reg_b);
             println!("Carry flag: {}", sap1.cf);
             println!("Zero flag: {}", sap1.zf);
+            print!("{}", sap1.stats.report());
             break;
         }
         match sap1.clock_mode {
             ClockMode::STEP => {
                 // wait for user to press 's' + Enter
-                println!("Press 's' to step, 'r' to run, 'q' to quit: ");
+                println!(
+                    "Press 's' to step, 'i' to step an instruction, 'r' to run, 'x' to reset, 'h' for debugger commands, 'q' to quit: "
+                );
                 let mut input = String::new();
                 std::io::stdin().read_line(&mut input).unwrap();
 
-                match input.trim() {
-                    "s" => sap1.clock_tick(),
-                    "r" => sap1.clock_mode = ClockMode::RUN,
-                    "q" => break,
+                let words: Vec<&str> = input.split_whitespace().collect();
+                match words.as_slice() {
+                    ["s"] => {
+                        sap1.clock_tick();
+                        report_break(&sap1);
+                    }
+                    ["i"] => {
+                        sap1.step_instruction();
+                        report_break(&sap1);
+                    }
+                    ["sb"] => {
+                        if !sap1.step_back() {
+                            println!("No history to step back through");
+                        }
+                    }
+                    ["ib"] => {
+                        if !sap1.can_step_back() {
+                            println!("No history to step back through");
+                        }
+                        sap1.step_back_instruction();
+                    }
+                    ["r"] => sap1.clock_mode = ClockMode::RUN,
+                    ["x"] => sap1.reset(),
+                    ["b"] => println!("Breakpoints: {:?}", sap1.breakpoints),
+                    ["b", address] => match parse_address(address) {
+                        Some(address) => {
+                            sap1.toggle_breakpoint(address);
+                            println!("Breakpoints: {:?}", sap1.breakpoints);
+                        }
+                        None => println!("Invalid address '{}'", address),
+                    },
+                    ["w"] => print_watchpoints(&sap1),
+                    ["w", address] | ["w", address, _] => {
+                        let kind = words.get(2).copied().unwrap_or("rw");
+                        match (parse_address(address), Watchpoint::parse(kind)) {
+                            (Some(address), Some(watchpoint)) => {
+                                sap1.toggle_watchpoint(address, watchpoint);
+                                print_watchpoints(&sap1);
+                            }
+                            (None, _) => println!("Invalid address '{}'", address),
+                            (_, None) => println!("Watch kind must be r, w or rw"),
+                        }
+                    }
+                    ["c"] => print_conditions(&sap1),
+                    ["c", ..] => match Condition::parse(&input.trim()[1..]) {
+                        Ok(condition) => {
+                            sap1.add_condition(condition);
+                            print_conditions(&sap1);
+                        }
+                        Err(e) => println!("Invalid condition: {}", e),
+                    },
+                    ["cd", index] => match index.parse::<usize>() {
+                        Ok(index) if index < sap1.conditions.len() => {
+                            sap1.conditions.remove(index);
+                            print_conditions(&sap1);
+                        }
+                        _ => println!("No condition {}", index),
+                    },
+                    ["h"] => {
+                        println!("sb, ib             step back a tick or an instruction");
+                        println!("b ADDR             toggle a breakpoint at ADDR, 'b' lists them");
+                        println!(
+                            "w ADDR [r|w|rw]    toggle a watchpoint on RAM reads/writes, 'w' lists them"
+                        );
+                        println!(
+                            "c COND             stop when COND becomes true, e.g. 'c A == 0 && CF'"
+                        );
+                        println!(
+                            "                   names: A B OUT CF ZF BUS T, 'c' lists conditions"
+                        );
+                        println!("cd N               delete condition N");
+                        println!("save FILE, load FILE  save or restore a machine snapshot");
+                        println!(
+                            "stats              tick and instruction counts, instruction mix and CPI"
+                        );
+                    }
+                    ["save", path] => match Snapshot::capture(&sap1).save(path) {
+                        Ok(()) => println!("Snapshot saved to {}", path),
+                        Err(e) => println!("Failed to save snapshot: {}", e),
+                    },
+                    ["load", path] => match Snapshot::load(path) {
+                        Ok(snapshot) => {
+                            snapshot.apply(&mut sap1);
+                            println!("Snapshot loaded from {}", path);
+                        }
+                        Err(e) => println!("Failed to load snapshot: {}", e),
+                    },
+                    ["stats"] => print!("{}", sap1.stats.report()),
+                    ["q"] => break,
                     _ => println!("Unknown command"),
                 }
             }
             ClockMode::RUN => {
                 sap1.clock_tick();
+                if sap1.break_reason.is_some() {
+                    report_break(&sap1);
+                    sap1.clock_mode = ClockMode::STEP;
+                }
                 std::thread::sleep(std::time::Duration::from_millis(100));
             }
         }
     }
+
+    save_waveform(&sap1, vcd_path);
+}
+
+fn save_waveform(sap1: &Sap1, vcd_path: Option<String>) {
+    if let (Some(path), Some(trace)) = (vcd_path, &sap1.trace) {
+        match vcd::save(&path, trace) {
+            Ok(()) => println!("Waveform of {} ticks written to {}", trace.len() - 1, path),
+            Err(e) => eprintln!("Failed to write waveform: {}", e),
+        }
+    }
+}
+
+fn report_break(sap1: &Sap1) {
+    if let Some(reason) = &sap1.break_reason {
+        println!("Stopped: {}", reason);
+    }
+}
+
+fn parse_address(text: &str) -> Option<u8> {
+    assembler::parse_number(text).and_then(|address| u8::try_from(address).ok())
+}
+
+fn print_watchpoints(sap1: &Sap1) {
+    let watched: Vec<String> = sap1
+        .watchpoints
+        .iter()
+        .map(|(address, watchpoint)| format!("{}:{}", address, watchpoint))
+        .collect();
+    println!("Watchpoints: [{}]", watched.join(", "));
+}
+
+fn print_conditions(sap1: &Sap1) {
+    println!("Conditions:");
+    for (i, condition) in sap1.conditions.iter().enumerate() {
+        println!("  {}: {}", i, condition.text);
+    }
 }
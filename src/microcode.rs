@@ -0,0 +1,559 @@
+use crate::assembler;
+use crate::emulator::ControlWord;
+use crate::isa::Instruction;
+use std::ops::RangeInclusive;
+
+// Number of micro-steps addressable by the 3-bit T-step counter
+pub const STEPS: usize = 8;
+// Number of flag input combinations (carry, zero) wired into the ROM address
+pub const FLAG_COMBINATIONS: usize = 4;
+
+// Micro-steps shared by every instruction: load the instruction at PC into IR
+const FETCH: [&str; 2] = ["CO MI", "RO II CE"];
+
+// Selects which flag inputs a micro-step applies to. `None` matches either state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlagMatch {
+    pub carry: Option<bool>,
+    pub zero: Option<bool>,
+}
+
+impl FlagMatch {
+    pub const ANY: FlagMatch = FlagMatch {
+        carry: None,
+        zero: None,
+    };
+
+    pub fn matches(&self, cf: bool, zf: bool) -> bool {
+        self.carry.is_none_or(|c| c == cf) && self.zero.is_none_or(|z| z == zf)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MicrocodeError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for MicrocodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// The control logic ROM. Just like the EEPROMs on the board, it is addressed by
+// the instruction register, the T-step counter and the flag inputs, and every
+// address holds one control word.
+#[derive(Debug, Clone)]
+pub struct MicrocodeRom {
+    words: Vec<ControlWord>,
+    // Instruction names, only used to label the table when it is saved or displayed
+    names: Vec<Option<String>>,
+}
+
+impl MicrocodeRom {
+    // A ROM with every location blank
+    pub fn empty() -> Self {
+        MicrocodeRom {
+            words: vec![ControlWord::default(); 256 * STEPS * FLAG_COMBINATIONS],
+            names: vec![None; 256],
+        }
+    }
+
+    fn index(opcode: u8, step: u8, cf: bool, zf: bool) -> usize {
+        let flags = ((cf as usize) << 1) | zf as usize;
+        ((opcode as usize * STEPS) + step as usize) * FLAG_COMBINATIONS + flags
+    }
+
+    pub fn get(&self, opcode: u8, step: u8, cf: bool, zf: bool) -> ControlWord {
+        if step as usize >= STEPS {
+            return ControlWord::default();
+        }
+        self.words[Self::index(opcode, step, cf, zf)]
+    }
+
+    pub fn set(&mut self, opcode: u8, step: u8, flags: FlagMatch, word: ControlWord) {
+        for cf in [false, true] {
+            for zf in [false, true] {
+                if flags.matches(cf, zf) {
+                    self.words[Self::index(opcode, step, cf, zf)] = word;
+                }
+            }
+        }
+    }
+
+    pub fn name(&self, opcode: u8) -> Option<&str> {
+        self.names[opcode as usize].as_deref()
+    }
+
+    // The micro-steps every opcode starts with
+    fn define_fetch(&mut self, steps: &[&str]) {
+        for opcode in 0..=255 {
+            self.define_steps(opcode, 0, steps);
+        }
+    }
+
+    // Program an instruction for a range of opcodes, starting right after the fetch cycle
+    fn define(&mut self, name: &str, opcodes: RangeInclusive<u8>, steps: &[&str]) {
+        for opcode in opcodes {
+            self.names[opcode as usize] = Some(name.to_string());
+            self.define_steps(opcode, FETCH.len() as u8, steps);
+        }
+    }
+
+    fn define_steps(&mut self, opcode: u8, first_step: u8, steps: &[&str]) {
+        for (i, signals) in steps.iter().enumerate() {
+            let word = ControlWord::from_signals(signals)
+                .expect("built-in microcode only uses known signals");
+            self.set(opcode, first_step + i as u8, FlagMatch::ANY, word);
+        }
+    }
+
+    fn define_jump_condition(&mut self, opcodes: RangeInclusive<u8>, flags: FlagMatch) {
+        let word = ControlWord::from_signals("RO J FLG").expect("known signals");
+        for opcode in opcodes {
+            self.set(opcode, 3, flags, word);
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::from_text(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, self.to_text())
+    }
+
+    // Parse a microcode table. Every line programs one instruction:
+    //
+    //   # comment
+    //   FETCH: 0: CO MI; 1: RO II CE
+    //   LDA_IMM: 2: CO MI; 3: RO AI CE; 4: PR
+    //   JPZ 0xE0-0xEF: 2: CO MI; 3: RO J; 3 Z: RO J FLG; 4: PR
+    //   DBL 0xF4: 2: AO BI; 3: EO AI FI; 4: PR
+    //
+    // FETCH applies to every opcode. Instructions of the stock set (NOP, LDA_MEM,
+    // LDA_IMM, STA, ...) may leave out their opcodes, new ones must give them.
+    // A step number may be followed by flag qualifiers (Z, !Z, C, !C) so it only
+    // applies to those flag inputs; later entries override earlier ones.
+    pub fn from_text(text: &str) -> Result<Self, MicrocodeError> {
+        let mut rom = MicrocodeRom::empty();
+
+        for (i, raw_line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let error = |message: String| MicrocodeError {
+                line: line_number,
+                message,
+            };
+
+            let line = raw_line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (header, body) = line
+                .split_once(':')
+                .ok_or_else(|| error("expected '<name> <opcodes>: <steps>'".to_string()))?;
+            let mut header_parts = header.split_whitespace();
+            let name = header_parts
+                .next()
+                .ok_or_else(|| error("missing instruction name".to_string()))?;
+            let opcodes = match header_parts.next() {
+                Some(opcodes) => parse_opcodes(opcodes).map_err(error)?,
+                None if name.eq_ignore_ascii_case("FETCH") => 0..=255,
+                None => match Instruction::from_table_name(name) {
+                    Some(inst) => inst.opcodes(),
+                    None => {
+                        return Err(error(format!(
+                            "'{}' is not a stock instruction, give its opcodes as in '{} 0xF4:'",
+                            name, name
+                        )));
+                    }
+                },
+            };
+            if let Some(extra) = header_parts.next() {
+                return Err(error(format!("unexpected '{}' after opcode", extra)));
+            }
+
+            let mut steps = Vec::new();
+            for entry in body.split(';') {
+                if entry.trim().is_empty() {
+                    continue;
+                }
+                steps.push(parse_step(entry).map_err(error)?);
+            }
+
+            for opcode in opcodes {
+                if !name.eq_ignore_ascii_case("FETCH") {
+                    rom.names[opcode as usize] = Some(name.to_string());
+                }
+                for &(step, flags, word) in &steps {
+                    rom.set(opcode, step, flags, word);
+                }
+            }
+        }
+
+        Ok(rom)
+    }
+
+    // Write the table back out in the format read by `from_text`
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# R-SAP-1 microcode\n");
+        text.push_str(&format!(
+            "# Signals: {}\n",
+            ControlWord::signal_names().join(" ")
+        ));
+        text.push_str("# <name> <opcode>[-<opcode>]: <step> [Z|!Z|C|!C]: <signals>; ...\n\n");
+
+        // Leading steps that are identical for every opcode and flag input
+        let mut fetch_len = 0;
+        while fetch_len < STEPS {
+            let first = self.words[Self::index(0, fetch_len as u8, false, false)];
+            let shared = (0..=255u8).all(|opcode| {
+                self.step_words(opcode, fetch_len as u8)
+                    .iter()
+                    .all(|&word| word == first)
+            });
+            if !shared || first == ControlWord::default() {
+                break;
+            }
+            fetch_len += 1;
+        }
+        if fetch_len > 0 {
+            let entries: Vec<String> = (0..fetch_len as u8)
+                .map(|step| self.step_entries(0, step).join("; "))
+                .collect();
+            text.push_str(&format!("FETCH: {}\n", entries.join("; ")));
+        }
+
+        let mut opcode = 0usize;
+        while opcode < 256 {
+            let entries: Vec<String> = (fetch_len as u8..STEPS as u8)
+                .flat_map(|step| self.step_entries(opcode as u8, step))
+                .collect();
+
+            // Group consecutive opcodes that share a name and identical microcode
+            let mut last = opcode;
+            while last < 255
+                && self.names[last + 1] == self.names[opcode]
+                && (fetch_len..STEPS).all(|step| {
+                    self.step_words(last as u8 + 1, step as u8)
+                        == self.step_words(opcode as u8, step as u8)
+                })
+            {
+                last += 1;
+            }
+
+            if !entries.is_empty() || self.names[opcode].is_some() {
+                let name = match &self.names[opcode] {
+                    Some(name) => name.clone(),
+                    None => format!("OP_{:02X}", opcode),
+                };
+                let opcodes = if last == opcode {
+                    format!("0x{:02X}", opcode)
+                } else {
+                    format!("0x{:02X}-0x{:02X}", opcode, last)
+                };
+                text.push_str(&format!("{} {}: {}\n", name, opcodes, entries.join("; ")));
+            }
+            opcode = last + 1;
+        }
+
+        text
+    }
+
+    // Control words for one step, indexed by `(cf << 1) | zf`
+    fn step_words(&self, opcode: u8, step: u8) -> [ControlWord; FLAG_COMBINATIONS] {
+        let start = Self::index(opcode, step, false, false);
+        let mut words = [ControlWord::default(); FLAG_COMBINATIONS];
+        words.copy_from_slice(&self.words[start..start + FLAG_COMBINATIONS]);
+        words
+    }
+
+    // Text entries for one step: the most common word for all flag inputs,
+    // followed by qualified overrides for the flag inputs that differ
+    fn step_entries(&self, opcode: u8, step: u8) -> Vec<String> {
+        let words = self.step_words(opcode, step);
+        // Ties go to the lowest flag input, so unconditional entries read naturally
+        let base = *words
+            .iter()
+            .rev()
+            .max_by_key(|&&word| words.iter().filter(|&&other| other == word).count())
+            .expect("four flag combinations");
+
+        let mut entries = Vec::new();
+        if base != ControlWord::default() {
+            entries.push(format!("{}: {}", step, base.to_signals()));
+        }
+
+        let mut done = [false; FLAG_COMBINATIONS];
+        for flags in 0..FLAG_COMBINATIONS {
+            if done[flags] || words[flags] == base {
+                continue;
+            }
+            let word = words[flags];
+            let matching: Vec<usize> = (0..FLAG_COMBINATIONS)
+                .filter(|&other| words[other] == word)
+                .collect();
+            let (cf, zf) = (flags & 2 != 0, flags & 1 != 0);
+            let qualifier = if matching == [flags & 1, (flags & 1) | 2] {
+                flag_qualifier("Z", zf)
+            } else if matching == [flags & 2, (flags & 2) | 1] {
+                flag_qualifier("C", cf)
+            } else {
+                done[flags] = true;
+                entries.push(format!(
+                    "{} {} {}: {}",
+                    step,
+                    flag_qualifier("C", cf),
+                    flag_qualifier("Z", zf),
+                    word.to_signals()
+                ));
+                continue;
+            };
+            for &other in &matching {
+                done[other] = true;
+            }
+            entries.push(format!("{} {}: {}", step, qualifier, word.to_signals()));
+        }
+
+        entries
+    }
+}
+
+fn flag_qualifier(flag: &str, set: bool) -> String {
+    if set {
+        flag.to_string()
+    } else {
+        format!("!{}", flag)
+    }
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    assembler::parse_number(text)
+        .and_then(|value| u8::try_from(value).ok())
+        .ok_or_else(|| format!("invalid number '{}'", text))
+}
+
+fn parse_opcodes(text: &str) -> Result<RangeInclusive<u8>, String> {
+    let (first, last) = match text.split_once('-') {
+        Some((first, last)) => (parse_byte(first)?, parse_byte(last)?),
+        None => {
+            let opcode = parse_byte(text)?;
+            (opcode, opcode)
+        }
+    };
+    if first > last {
+        return Err(format!("opcode range '{}' is reversed", text));
+    }
+    Ok(first..=last)
+}
+
+fn parse_step(entry: &str) -> Result<(u8, FlagMatch, ControlWord), String> {
+    let (address, signals) = entry
+        .split_once(':')
+        .ok_or_else(|| format!("expected '<step>: <signals>' in '{}'", entry.trim()))?;
+
+    let mut parts = address.split_whitespace();
+    let step_text = parts.next().ok_or("missing step number")?;
+    let step = parse_byte(step_text)?;
+    if step as usize >= STEPS {
+        return Err(format!(
+            "step {} is beyond the {} step T counter",
+            step, STEPS
+        ));
+    }
+
+    let mut flags = FlagMatch::ANY;
+    for qualifier in parts {
+        match qualifier.to_ascii_uppercase().as_str() {
+            "Z" => flags.zero = Some(true),
+            "!Z" => flags.zero = Some(false),
+            "C" => flags.carry = Some(true),
+            "!C" => flags.carry = Some(false),
+            _ => return Err(format!("unknown flag qualifier '{}'", qualifier)),
+        }
+    }
+
+    Ok((step, flags, ControlWord::from_signals(signals)?))
+}
+
+impl Default for MicrocodeRom {
+    // The stock R-SAP-1 instruction set
+    fn default() -> Self {
+        let mut rom = MicrocodeRom::empty();
+
+        rom.define_fetch(&FETCH);
+
+        rom.define("NOP", 0x00..=0x0F, &["PR"]);
+        rom.define(
+            "LDA_MEM",
+            0x10..=0x1F,
+            &["CO MI", "RO MI", "RO AI CE", "PR"],
+        );
+        rom.define("LDA_IMM", 0x20..=0x2F, &["CO MI", "RO AI CE", "PR"]);
+        rom.define(
+            "LDB_MEM",
+            0x30..=0x3F,
+            &["CO MI", "RO MI", "RO BI CE", "PR"],
+        );
+        rom.define("LDB_IMM", 0x40..=0x4F, &["CO MI", "RO BI CE", "PR"]);
+        rom.define(
+            "ADD_MEM",
+            0x50..=0x5F,
+            &["CO MI", "RO MI", "RO BI", "EO AI FI CE", "PR"],
+        );
+        rom.define(
+            "ADD_IMM",
+            0x60..=0x6F,
+            &["CO MI", "RO BI", "EO AI FI CE", "PR"],
+        );
+        rom.define(
+            "SUB_MEM",
+            0x70..=0x7F,
+            &["CO MI", "RO MI", "RO BI SU", "EO AI SU FI CE", "PR"],
+        );
+        rom.define(
+            "SUB_IMM",
+            0x80..=0x8F,
+            &["CO MI", "RO BI SU", "EO AI SU FI CE", "PR"],
+        );
+        rom.define("STA", 0x90..=0x9F, &["CO MI", "RO MI CE", "AO RI", "PR"]);
+        rom.define("JMP", 0xA0..=0xAF, &["CO MI", "RO J FLG", "PR"]);
+        rom.define(
+            "CMP_MEM",
+            0xB0..=0xBF,
+            &["CO MI", "RO MI", "RO BI CE", "SU FI", "PR"],
+        );
+        rom.define(
+            "CMP_IMM",
+            0xC0..=0xCF,
+            &["CO MI", "RO BI CE", "SU FI", "PR"],
+        );
+        rom.define("BNE", 0xD0..=0xDF, &["CO MI", "RO J", "PR"]);
+        rom.define_jump_condition(
+            0xD0..=0xDF,
+            FlagMatch {
+                zero: Some(false),
+                ..FlagMatch::ANY
+            },
+        );
+        rom.define("JPZ", 0xE0..=0xEF, &["CO MI", "RO J", "PR"]);
+        rom.define_jump_condition(
+            0xE0..=0xEF,
+            FlagMatch {
+                zero: Some(true),
+                ..FlagMatch::ANY
+            },
+        );
+        rom.define("JPC", 0xF0..=0xF0, &["CO MI", "RO J", "PR"]);
+        rom.define_jump_condition(
+            0xF0..=0xF0,
+            FlagMatch {
+                carry: Some(true),
+                ..FlagMatch::ANY
+            },
+        );
+        // INC and DEC modify memory in place, passing the value through A
+        rom.define(
+            "INC",
+            0xF1..=0xF1,
+            &["CO MI", "RO MI CE", "RO AI", "EO AI ONE FI", "AO RI", "PR"],
+        );
+        rom.define(
+            "DEC",
+            0xF2..=0xF2,
+            &[
+                "CO MI",
+                "RO MI CE",
+                "RO AI",
+                "EO AI SU ONE FI",
+                "AO RI",
+                "PR",
+            ],
+        );
+        rom.define("OUT", 0xF3..=0xF3, &["AO OI", "PR"]);
+        rom.define("HLT", 0xFF..=0xFF, &["HLT", "PR"]);
+
+        rom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> (usize, String) {
+        let error = MicrocodeRom::from_text(text).expect_err("table has errors");
+        (error.line, error.message)
+    }
+
+    #[test]
+    fn default_table_round_trips_through_text() {
+        let rom = MicrocodeRom::default();
+        let text = rom.to_text();
+        let parsed = MicrocodeRom::from_text(&text).unwrap();
+        assert_eq!(parsed.to_text(), text);
+        for opcode in 0..=255 {
+            assert_eq!(parsed.name(opcode), rom.name(opcode));
+            for step in 0..STEPS as u8 {
+                for (cf, zf) in [(false, false), (false, true), (true, false), (true, true)] {
+                    assert_eq!(
+                        parsed.get(opcode, step, cf, zf),
+                        rom.get(opcode, step, cf, zf)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bare_names_take_their_opcodes_from_the_instruction_set() {
+        let rom = MicrocodeRom::from_text("LDA_IMM: 2: CO MI; 3: RO AI CE; 4: PR").unwrap();
+        let load = ControlWord::from_signals("RO AI CE").unwrap();
+        assert_eq!(rom.get(0x20, 3, false, false), load);
+        assert_eq!(rom.get(0x2F, 3, true, true), load);
+        assert_eq!(rom.get(0x30, 3, false, false), ControlWord::default());
+        assert_eq!(rom.name(0x2F), Some("LDA_IMM"));
+    }
+
+    #[test]
+    fn flag_qualifiers_only_program_matching_inputs() {
+        let rom = MicrocodeRom::from_text("JPZ 0xE0: 3: RO J; 3 Z: RO J FLG; 3 C !Z: PR").unwrap();
+        assert!(rom.get(0xE0, 3, false, true).FLG);
+        assert!(rom.get(0xE0, 3, true, true).FLG);
+        assert!(!rom.get(0xE0, 3, false, false).FLG);
+        assert_eq!(
+            rom.get(0xE0, 3, true, false),
+            ControlWord::from_signals("PR").unwrap()
+        );
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        assert_eq!(
+            error("# custom\nFOO: 2: PR"),
+            (
+                2,
+                "'FOO' is not a stock instruction, give its opcodes as in 'FOO 0xF4:'".to_string()
+            )
+        );
+        assert_eq!(
+            error("NOP: 8: PR"),
+            (1, "step 8 is beyond the 8 step T counter".to_string())
+        );
+        assert_eq!(
+            error("NOP 0x0F-0x00: 2: PR"),
+            (1, "opcode range '0x0F-0x00' is reversed".to_string())
+        );
+        assert_eq!(
+            error("\nNOP: 2: PR XX"),
+            (2, "unknown control signal 'XX'".to_string())
+        );
+        assert_eq!(
+            error("NOP: 2 Q: PR"),
+            (1, "unknown flag qualifier 'Q'".to_string())
+        );
+    }
+}
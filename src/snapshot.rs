@@ -0,0 +1,221 @@
+use crate::assembler;
+use crate::emulator::{ClockMode, ControlWord, MachineState, Sap1};
+use crate::microcode::STEPS;
+
+// Bumped whenever a field is added or its meaning changes
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+// The complete machine at one instant, saved as a text file so it can be read
+// and tweaked by hand:
+//
+//   # R-SAP-1 snapshot
+//   version: 1
+//   clock: STEP
+//   a: 110
+//   ...
+//   control: RO AI CE
+//   memory 0x00: 20 05 F3 FF ...
+//
+// Registers are decimal, memory is 16 hex bytes per line.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub state: MachineState,
+    pub clock_mode: ClockMode,
+}
+
+impl Snapshot {
+    pub fn capture(sap1: &Sap1) -> Self {
+        Snapshot {
+            state: sap1.state(),
+            clock_mode: sap1.clock_mode,
+        }
+    }
+
+    // Replace the machine state. Breakpoints, microcode and the like are kept,
+    // the step back history is dropped.
+    pub fn apply(&self, sap1: &mut Sap1) {
+        sap1.reset();
+        sap1.restore(&self.state);
+        sap1.clock_mode = self.clock_mode;
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::from_text(&text).map_err(|e| format!("{}:{}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_text()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn to_text(&self) -> String {
+        let state = &self.state;
+        let clock = match self.clock_mode {
+            ClockMode::RUN => "RUN",
+            ClockMode::STEP => "STEP",
+        };
+        let mut text = String::from("# R-SAP-1 snapshot\n");
+        text.push_str(&format!("version: {}\n", SNAPSHOT_VERSION));
+        text.push_str(&format!("clock: {}\n", clock));
+        for (key, value) in [
+            ("a", state.reg_a),
+            ("b", state.reg_b),
+            ("alu", state.alu_out),
+            ("out", state.output),
+            ("pc", state.pc),
+            ("bus", state.bus),
+            ("mar", state.mar),
+            ("ir", state.ir),
+            ("t_step", state.t_step),
+        ] {
+            text.push_str(&format!("{}: {}\n", key, value));
+        }
+        for (key, value) in [("cf", state.cf), ("zf", state.zf), ("hlt", state.hlt)] {
+            text.push_str(&format!("{}: {}\n", key, value));
+        }
+        text.push_str(&format!("control: {}\n", state.control_word.to_signals()));
+        for (row, bytes) in state.memory.chunks(16).enumerate() {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            text.push_str(&format!("memory 0x{:02X}: {}\n", row * 16, bytes.join(" ")));
+        }
+        text
+    }
+
+    // Parse a snapshot. Fields that are left out keep their power-on value,
+    // errors are prefixed with the line number.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut snapshot = Snapshot::capture(&Sap1::new());
+        let mut version = None;
+
+        for (i, raw_line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let line = raw_line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| format!("{}: expected '<field>: <value>'", line_number))?;
+            let (key, value) = (key.trim(), value.trim());
+            let error = |message: String| format!("{}: {}", line_number, message);
+
+            if version.is_none() && key != "version" {
+                return Err(error("snapshot must start with 'version'".to_string()));
+            }
+
+            let state = &mut snapshot.state;
+            match key {
+                "version" => {
+                    let number = value
+                        .parse::<u32>()
+                        .map_err(|_| error(format!("invalid version '{}'", value)))?;
+                    if number > SNAPSHOT_VERSION {
+                        return Err(error(format!(
+                            "snapshot version {} is newer than this emulator supports ({})",
+                            number, SNAPSHOT_VERSION
+                        )));
+                    }
+                    version = Some(number);
+                }
+                "clock" => {
+                    snapshot.clock_mode = match value {
+                        "RUN" => ClockMode::RUN,
+                        "STEP" => ClockMode::STEP,
+                        _ => return Err(error(format!("unknown clock mode '{}'", value))),
+                    }
+                }
+                "a" => state.reg_a = parse_byte(value).map_err(error)?,
+                "b" => state.reg_b = parse_byte(value).map_err(error)?,
+                "alu" => state.alu_out = parse_byte(value).map_err(error)?,
+                "out" => state.output = parse_byte(value).map_err(error)?,
+                "pc" => state.pc = parse_byte(value).map_err(error)?,
+                "bus" => state.bus = parse_byte(value).map_err(error)?,
+                "mar" => state.mar = parse_byte(value).map_err(error)?,
+                "ir" => state.ir = parse_byte(value).map_err(error)?,
+                "t_step" => {
+                    state.t_step = parse_byte(value)
+                        .ok()
+                        .filter(|&step| (step as usize) < STEPS)
+                        .ok_or_else(|| error(format!("t_step must be 0 to {}", STEPS - 1)))?
+                }
+                "cf" => state.cf = parse_bool(value).map_err(error)?,
+                "zf" => state.zf = parse_bool(value).map_err(error)?,
+                "hlt" => state.hlt = parse_bool(value).map_err(error)?,
+                "control" => {
+                    state.control_word = ControlWord::from_signals(value).map_err(error)?
+                }
+                _ => {
+                    let address = key
+                        .strip_prefix("memory")
+                        .and_then(|address| parse_byte(address.trim()).ok())
+                        .ok_or_else(|| error(format!("unknown field '{}'", key)))?
+                        as usize;
+                    let bytes = value
+                        .split_whitespace()
+                        .map(|byte| u8::from_str_radix(byte, 16))
+                        .collect::<Result<Vec<u8>, _>>()
+                        .map_err(|_| error("memory bytes must be hex".to_string()))?;
+                    if address + bytes.len() > state.memory.len() {
+                        return Err(error("memory row runs past address 255".to_string()));
+                    }
+                    state.memory[address..address + bytes.len()].copy_from_slice(&bytes);
+                }
+            }
+        }
+
+        if version.is_none() {
+            return Err("1: empty snapshot".to_string());
+        }
+        Ok(snapshot)
+    }
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    assembler::parse_number(text)
+        .and_then(|value| u8::try_from(value).ok())
+        .ok_or_else(|| format!("'{}' is not a value from 0 to 255", text))
+}
+
+fn parse_bool(text: &str) -> Result<bool, String> {
+    match text {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(format!("'{}' is not true or false", text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_text() {
+        let mut sap1 = Sap1::new();
+        let program = assembler::assemble("LDA # 200\nADD # 100\nOUT\nHLT").unwrap();
+        sap1.load_program(&program).unwrap();
+        sap1.run(10);
+        assert!(sap1.cf);
+        sap1.clock_mode = ClockMode::RUN;
+
+        let snapshot = Snapshot::capture(&sap1);
+        let parsed = Snapshot::from_text(&snapshot.to_text()).unwrap();
+        assert_eq!(parsed.state, sap1.state());
+        assert_eq!(parsed.clock_mode, ClockMode::RUN);
+    }
+
+    #[test]
+    fn rejects_bad_snapshots() {
+        assert_eq!(
+            Snapshot::from_text("version: 2\na: 1").unwrap_err(),
+            "1: snapshot version 2 is newer than this emulator supports (1)"
+        );
+        assert_eq!(
+            Snapshot::from_text("a: 1\nversion: 1").unwrap_err(),
+            "1: snapshot must start with 'version'"
+        );
+        assert_eq!(
+            Snapshot::from_text("version: 1\nt_step: 8").unwrap_err(),
+            "2: t_step must be 0 to 7"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use crate::isa::Instruction;
+
+// Execution counters, cleared by reset and rewound by stepping back
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub ticks: u64,
+    // Instructions that ran back to T0 or halted the CPU
+    pub instructions: u64,
+    // Retired instructions and the ticks they took, fetch included, by opcode
+    pub opcode_count: [u64; 256],
+    pub opcode_ticks: [u64; 256],
+    // Ticks of the instruction in progress, credited to its opcode when it retires
+    current_ticks: u64,
+}
+
+// The totals before a clock tick, kept in the step back history so the tick
+// can be taken back out of the counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatsMark {
+    ticks: u64,
+    instructions: u64,
+    current_ticks: u64,
+}
+
+// One line of the instruction mix
+#[derive(Debug, Clone, PartialEq)]
+pub struct MixEntry {
+    pub name: String,
+    pub count: u64,
+    pub ticks: u64,
+}
+
+impl MixEntry {
+    pub fn cpi(&self) -> f64 {
+        self.ticks as f64 / self.count as f64
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            ticks: 0,
+            instructions: 0,
+            opcode_count: [0; 256],
+            opcode_ticks: [0; 256],
+            current_ticks: 0,
+        }
+    }
+}
+
+impl Stats {
+    // Count one clock tick. `retired` is set when it finished the instruction in IR.
+    pub fn record_tick(&mut self, opcode: u8, retired: bool) {
+        self.ticks += 1;
+        self.current_ticks += 1;
+        if retired {
+            self.instructions += 1;
+            self.opcode_count[opcode as usize] += 1;
+            self.opcode_ticks[opcode as usize] += self.current_ticks;
+            self.current_ticks = 0;
+        }
+    }
+
+    pub fn mark(&self) -> StatsMark {
+        StatsMark {
+            ticks: self.ticks,
+            instructions: self.instructions,
+            current_ticks: self.current_ticks,
+        }
+    }
+
+    // Return to the totals at `mark`. `opcode` is the instruction in IR after
+    // the ticks being undone, the one credited if they retired it.
+    pub fn rewind(&mut self, mark: StatsMark, opcode: u8) {
+        if self.instructions > mark.instructions {
+            let ticks = mark.current_ticks + (self.ticks - mark.ticks);
+            self.opcode_count[opcode as usize] -= 1;
+            self.opcode_ticks[opcode as usize] -= ticks;
+        }
+        self.ticks = mark.ticks;
+        self.instructions = mark.instructions;
+        self.current_ticks = mark.current_ticks;
+    }
+
+    // Average clock ticks per retired instruction
+    pub fn cpi(&self) -> Option<f64> {
+        (self.instructions > 0).then(|| self.retired_ticks() as f64 / self.instructions as f64)
+    }
+
+    fn retired_ticks(&self) -> u64 {
+        self.ticks - self.current_ticks
+    }
+
+    // Retired instructions grouped the way the disassembler names them, most
+    // frequent first. Opcodes outside the instruction set are listed by value.
+    pub fn mix(&self) -> Vec<MixEntry> {
+        let mut mix: Vec<MixEntry> = Vec::new();
+        for opcode in 0..=255u8 {
+            let count = self.opcode_count[opcode as usize];
+            if count == 0 {
+                continue;
+            }
+            let name = match Instruction::decode(opcode) {
+                Some(inst) => inst.syntax(),
+                None => format!("0x{:02X}", opcode),
+            };
+            let ticks = self.opcode_ticks[opcode as usize];
+            match mix.iter_mut().find(|entry| entry.name == name) {
+                Some(entry) => {
+                    entry.count += count;
+                    entry.ticks += ticks;
+                }
+                None => mix.push(MixEntry { name, count, ticks }),
+            }
+        }
+        mix.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+        mix
+    }
+
+    // One line totals, e.g. "179 ticks, 35 instructions, CPI 5.11"
+    pub fn summary(&self) -> String {
+        format!(
+            "{} ticks, {} instructions, CPI {}",
+            self.ticks,
+            self.instructions,
+            self.cpi()
+                .map_or("-".to_string(), |cpi| format!("{:.2}", cpi))
+        )
+    }
+
+    // Plain text report for the terminal
+    pub fn report(&self) -> String {
+        let mut text = format!("{}\n", self.summary());
+        for entry in self.mix() {
+            text.push_str(&format!(
+                "  {:<6} {:>8} {:>5.1}% {:>10} ticks  CPI {:.2}\n",
+                entry.name,
+                entry.count,
+                entry.count as f64 * 100.0 / self.instructions as f64,
+                entry.ticks,
+                entry.cpi()
+            ));
+        }
+        text
+    }
+}
@@ -1,46 +1,441 @@
-use crate::emulator::Sap1;
+use crate::assembler;
+use crate::debugger::Condition;
+use crate::emulator::{ClockMode, IllegalOpcodePolicy, Sap1, Settings};
+use crate::isa::Instruction;
+use crate::loader;
+use crate::microcode::MicrocodeRom;
+use crate::snapshot::Snapshot;
+use crate::validator;
+use crate::vcd;
 use eframe::egui;
+use std::time::{Duration, Instant};
+
+// Longest a single frame may spend ticking the clock at maximum speed
+const MAX_SPEED_FRAME_TIME: Duration = Duration::from_millis(15);
 
 pub struct Sap1UI {
     emulator: Sap1,
+    // File the microcode was loaded from, if not the built-in table
+    microcode_path: Option<String>,
+    microcode_status: String,
+    // Findings of the last microcode check, shown until dismissed
+    microcode_issues: Option<Vec<validator::Issue>>,
+    // Clock frequency in RUN mode
+    clock_hz: f64,
+    // Ignore the frequency and tick as fast as possible
+    max_speed: bool,
+    // Time of the last frame while running, and the ticks owed since then
+    last_run_time: Option<f64>,
+    pending_ticks: f64,
+    // Result of the last open or save
+    file_status: String,
+    // Memory row being edited in the memory view
+    memory_edit: Option<MemoryEdit>,
+    // Program mode: the address switches select the RAM address in place of
+    // the MAR and the write button stores the data switches, with the clock stopped
+    program_mode: bool,
+    address_switches: u8,
+    data_switches: u8,
+    // Break condition being typed, and why the last one was rejected
+    condition_text: String,
+    condition_error: Option<String>,
+}
+
+struct MemoryEdit {
+    address: usize,
+    text: String,
+    error: Option<String>,
+    request_focus: bool,
+}
+
+impl MemoryEdit {
+    fn new(address: usize) -> Self {
+        MemoryEdit {
+            address,
+            text: String::new(),
+            error: None,
+            request_focus: true,
+        }
+    }
 }
 
 impl Sap1UI {
-    pub fn new() -> Self {
+    pub fn new(
+        microcode: MicrocodeRom,
+        microcode_path: Option<String>,
+        program: Option<Vec<u8>>,
+        snapshot: Option<Snapshot>,
+        settings: Settings,
+    ) -> Self {
         let mut emulator = Sap1::new();
+        emulator.microcode = microcode;
+        emulator.settings = settings;
+
+        let program = program.unwrap_or_else(|| {
+            assembler::assemble(include_str!("../programs/demo.asm"))
+                .expect("built-in demo program assembles")
+                .to_vec()
+        });
+        emulator
+            .load_program(&program)
+            .expect("loader rejects oversized programs");
+        if let Some(snapshot) = snapshot {
+            snapshot.apply(&mut emulator);
+        }
+
+        Self {
+            emulator,
+            microcode_status: match &microcode_path {
+                Some(path) => format!("Loaded {}", path),
+                None => "Built-in".to_string(),
+            },
+            microcode_path,
+            microcode_issues: None,
+            clock_hz: 5.0,
+            max_speed: false,
+            last_run_time: None,
+            pending_ticks: 0.0,
+            file_status: String::new(),
+            memory_edit: None,
+            program_mode: false,
+            address_switches: 0,
+            data_switches: 0,
+            condition_text: String::new(),
+            condition_error: None,
+        }
+    }
+
+    fn set_program_mode(&mut self, enabled: bool) {
+        self.program_mode = enabled;
+        if enabled {
+            self.stop_clock();
+            self.address_switches = self.emulator.mar;
+        }
+    }
+
+    // The RAM address on the display. In program mode the address switches
+    // are selected instead of the MAR, which keeps its value.
+    fn memory_address(&self) -> u8 {
+        if self.program_mode {
+            self.address_switches
+        } else {
+            self.emulator.mar
+        }
+    }
+
+    // Text entry for the memory row being edited. Enter writes the value and
+    // moves on to the next free address, Escape cancels.
+    fn show_memory_editor(&mut self, ui: &mut egui::Ui) {
+        let Some(edit) = &mut self.memory_edit else {
+            return;
+        };
+
+        let response = ui.add(
+            egui::TextEdit::singleline(&mut edit.text)
+                .desired_width(120.0)
+                .hint_text("0b.., 0x.. or ADD # 5"),
+        );
+        if edit.request_focus {
+            response.request_focus();
+            edit.request_focus = false;
+        }
+        if let Some(error) = &edit.error {
+            ui.colored_label(egui::Color32::from_rgb(220, 50, 50), error);
+        }
+
+        if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+            self.memory_edit = None;
+        } else if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+            let address = edit.address;
+            match parse_memory_entry(&edit.text) {
+                Ok(bytes) if address + bytes.len() > self.emulator.memory.len() => {
+                    edit.error = Some("does not fit in memory".to_string());
+                    edit.request_focus = true;
+                }
+                Ok(bytes) => {
+                    self.emulator.write_memory(address, &bytes);
+                    let next = address + bytes.len();
+                    self.memory_edit =
+                        (next < self.emulator.memory.len()).then(|| MemoryEdit::new(next));
+                }
+                Err(e) => {
+                    edit.error = Some(e);
+                    edit.request_focus = true;
+                }
+            }
+        }
+    }
+
+    // Reset the machine and clear RAM, then load the program at `path`. The
+    // microcode, settings, breakpoints, watchpoints and conditions are kept,
+    // and an active waveform recording starts over with the new program.
+    fn open_program(&mut self, path: &str) {
+        match loader::load_program_file(path) {
+            Ok(program) => {
+                self.stop_clock();
+                self.emulator.reset();
+                self.emulator.memory = [0; 256];
+                self.emulator
+                    .load_program(&program)
+                    .expect("loader rejects oversized programs");
+                if self.emulator.trace.is_some() {
+                    self.emulator.start_trace();
+                }
+                self.memory_edit = None;
+                self.file_status = format!("Loaded {}", path);
+            }
+            Err(e) => self.file_status = e,
+        }
+    }
+
+    fn open_program_dialog(&mut self) {
+        let picked = rfd::FileDialog::new()
+            .add_filter("Programs", &["asm", "bin", "hex"])
+            .add_filter("All files", &["*"])
+            .pick_file();
+        if let Some(path) = picked {
+            self.open_program(&path.to_string_lossy());
+        }
+    }
+
+    // Break conditions: a list with remove buttons and a field to add new ones
+    fn show_conditions(&mut self, ui: &mut egui::Ui) {
+        let mut remove = None;
+        for (i, condition) in self.emulator.conditions.iter().enumerate() {
+            ui.horizontal(|ui| {
+                if ui.small_button("x").clicked() {
+                    remove = Some(i);
+                }
+                ui.monospace(&condition.text);
+            });
+        }
+        if let Some(i) = remove {
+            self.emulator.conditions.remove(i);
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Break when:");
+            let field = ui.add(
+                egui::TextEdit::singleline(&mut self.condition_text)
+                    .hint_text("A == 0 && CF")
+                    .desired_width(120.0)
+                    .font(egui::TextStyle::Monospace),
+            );
+            let entered = field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if (ui.button("Add").clicked() || entered) && !self.condition_text.trim().is_empty() {
+                match Condition::parse(&self.condition_text) {
+                    Ok(condition) => {
+                        self.emulator.add_condition(condition);
+                        self.condition_text.clear();
+                        self.condition_error = None;
+                    }
+                    Err(e) => self.condition_error = Some(e),
+                }
+            }
+        })
+        .response
+        .on_hover_text("Names: A, B, OUT, CF, ZF, BUS, T. Operators: == != < <= > >= && || !");
+        if let Some(error) = &self.condition_error {
+            ui.colored_label(egui::Color32::from_rgb(220, 60, 60), error);
+        }
+    }
+
+    // Tick and instruction counters with the instruction mix
+    fn show_stats(&mut self, ui: &mut egui::Ui) {
+        let summary = self.emulator.stats.summary();
+        ui.horizontal(|ui| {
+            ui.label(summary);
+            if ui.small_button("Clear").clicked() {
+                self.emulator.clear_stats();
+            }
+        });
+
+        let stats = &self.emulator.stats;
+        egui::ScrollArea::vertical()
+            .id_salt("instruction_mix")
+            .show(ui, |ui| {
+                egui::Grid::new("instruction_mix_grid")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for heading in ["Instruction", "Count", "Mix", "Ticks", "CPI"] {
+                            ui.strong(heading);
+                        }
+                        ui.end_row();
+                        for entry in stats.mix() {
+                            ui.monospace(&entry.name);
+                            ui.monospace(entry.count.to_string());
+                            ui.monospace(format!(
+                                "{:.1}%",
+                                entry.count as f64 * 100.0 / stats.instructions as f64
+                            ));
+                            ui.monospace(entry.ticks.to_string());
+                            ui.monospace(format!("{:.2}", entry.cpi()));
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
 
-        let program = [
-            // Test 1: Basic arithmetic and JNZ
-            0x20, 5, // 00-01: LDA # 5        (Load 5 into A)
-            0x80, 5, // 02-03: SUB # 5        (Subtract 5, A = 0, ZF = 1)
-            0xD0, 10, // 04-05: JNZ 10         (Should NOT jump, ZF = 1)
-            0x20, 99,   // 06-07: LDA # 99       (This should execute)
-            0xF3, // 08:    OUT            (Output 99)
-            0xA0, 14, // 09-10: JMP 14         (Jump to next test)
-            // If JNZ incorrectly jumped here:
-            0x20, 255,  // 11-12: LDA # 255      (This should NOT execute)
-            0xF3, // 13:    OUT            (Should not output 255)
-            // Test 2: JPZ (jump if zero)
-            0x20, 10, // 14-15: LDA # 10       (Load 10, ZF = 0)
-            0xE0, 24, // 16-17: JPZ 24         (Should NOT jump, ZF = 0)
-            0x20, 0, // 18-19: LDA # 0        (Load 0, ZF = 1)
-            0xE0, 26, // 20-21: JPZ 26         (Should jump, ZF = 1)
-            0x20, 111,  // 22-23: LDA # 111      (Should NOT execute)
-            0xF3, // 24:    OUT            (Should NOT output 111)
-            0xFF, // 25:    HLT            (Should NOT halt here)
-            // Test 3: Successful jump lands here
-            0x20, 42,   // 26-27: LDA # 42       (This should execute)
-            0xF3, // 28:    OUT            (Output 42)
-            // Test 4: Simple addition
-            0x20, 10, // 29-30: LDA # 10
-            0x60, 5,    // 31-32: ADD # 5        (A = 15)
-            0xF3, // 33:    OUT            (Output 15)
-            0xFF, // 34:    HLT            (End)
-        ];
-
-        emulator.load_program(&program);
-
-        Self { emulator }
+    fn save_memory_dialog(&mut self) {
+        let picked = rfd::FileDialog::new()
+            .add_filter("Binary image", &["bin"])
+            .add_filter("Hex text", &["hex"])
+            .set_file_name("memory.bin")
+            .save_file();
+        if let Some(path) = picked {
+            let path = path.to_string_lossy();
+            self.file_status = match loader::save_memory_image(&path, &self.emulator.memory) {
+                Ok(()) => format!("Saved {}", path),
+                Err(e) => e,
+            };
+        }
+    }
+
+    fn load_snapshot(&mut self, path: &str) {
+        match Snapshot::load(path) {
+            Ok(snapshot) => {
+                snapshot.apply(&mut self.emulator);
+                if self.is_running() {
+                    self.start_clock();
+                }
+                self.memory_edit = None;
+                self.file_status = format!("Loaded snapshot {}", path);
+            }
+            Err(e) => self.file_status = e,
+        }
+    }
+
+    fn load_snapshot_dialog(&mut self) {
+        let picked = rfd::FileDialog::new()
+            .add_filter("Snapshots", &["snap"])
+            .add_filter("All files", &["*"])
+            .pick_file();
+        if let Some(path) = picked {
+            self.load_snapshot(&path.to_string_lossy());
+        }
+    }
+
+    fn save_snapshot_dialog(&mut self) {
+        let picked = rfd::FileDialog::new()
+            .add_filter("Snapshots", &["snap"])
+            .set_file_name("machine.snap")
+            .save_file();
+        if let Some(path) = picked {
+            let path = path.to_string_lossy();
+            self.file_status = match Snapshot::capture(&self.emulator).save(&path) {
+                Ok(()) => format!("Saved snapshot {}", path),
+                Err(e) => e,
+            };
+        }
+    }
+
+    fn save_waveform_dialog(&mut self) {
+        let Some(trace) = &self.emulator.trace else {
+            return;
+        };
+        let picked = rfd::FileDialog::new()
+            .add_filter("Value change dump", &["vcd"])
+            .set_file_name("waveform.vcd")
+            .save_file();
+        if let Some(path) = picked {
+            let path = path.to_string_lossy();
+            self.file_status = match vcd::save(&path, trace) {
+                Ok(()) => format!("Saved {} ticks to {}", trace.len() - 1, path),
+                Err(e) => e,
+            };
+        }
+    }
+
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped = ctx.input(|i| i.raw.dropped_files.clone());
+        if let Some(path) = dropped.iter().find_map(|file| file.path.as_ref()) {
+            if path.extension().is_some_and(|ext| ext == "snap") {
+                self.load_snapshot(&path.to_string_lossy());
+            } else {
+                self.open_program(&path.to_string_lossy());
+            }
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        matches!(self.emulator.clock_mode, ClockMode::RUN)
+    }
+
+    // Run from the current state, continuing past whatever stopped the clock last
+    fn start_clock(&mut self) {
+        self.emulator.break_reason = None;
+        self.emulator.clock_mode = ClockMode::RUN;
+        self.last_run_time = None;
+        self.pending_ticks = 0.0;
+    }
+
+    fn stop_clock(&mut self) {
+        self.emulator.clock_mode = ClockMode::STEP;
+    }
+
+    // Advance the clock by however many ticks are due since the last frame
+    fn run_clock(&mut self, ctx: &egui::Context) {
+        if !self.is_running() {
+            return;
+        }
+        if self.emulator.hlt {
+            self.stop_clock();
+            return;
+        }
+
+        if self.max_speed {
+            self.last_run_time = None;
+            let start = Instant::now();
+            while self.can_tick() && start.elapsed() < MAX_SPEED_FRAME_TIME {
+                self.emulator.clock_tick();
+            }
+            ctx.request_repaint();
+        } else {
+            let now = ctx.input(|i| i.time);
+            if let Some(last) = self.last_run_time {
+                self.pending_ticks += (now - last) * self.clock_hz;
+            } else {
+                // Tick right away when the clock starts
+                self.pending_ticks = 1.0;
+            }
+            self.last_run_time = Some(now);
+
+            let start = Instant::now();
+            while self.pending_ticks >= 1.0 && self.can_tick() {
+                self.emulator.clock_tick();
+                self.pending_ticks -= 1.0;
+                if start.elapsed() >= MAX_SPEED_FRAME_TIME {
+                    // Can't keep up, drop the backlog rather than stall the UI
+                    self.pending_ticks = 0.0;
+                }
+            }
+            // Ticks still owed when HLT or a break cut the loop short make this
+            // negative, so the next frame is simply due right away
+            let until_next_tick = ((1.0 - self.pending_ticks) / self.clock_hz).max(0.0);
+            ctx.request_repaint_after(Duration::from_secs_f64(until_next_tick));
+        }
+
+        if !self.can_tick() {
+            self.stop_clock();
+        }
+    }
+
+    // Whether a running clock should keep ticking
+    fn can_tick(&self) -> bool {
+        !self.emulator.hlt && self.emulator.break_reason.is_none()
+    }
+
+    fn reload_microcode(&mut self) {
+        if let Some(path) = &self.microcode_path {
+            match MicrocodeRom::load(path) {
+                Ok(rom) => {
+                    self.emulator.microcode = rom;
+                    self.microcode_status = format!("Reloaded {}", path);
+                    self.microcode_issues = None;
+                }
+                Err(e) => self.microcode_status = e,
+            }
+        }
     }
 }
 
@@ -64,6 +459,53 @@ impl LedColor {
 
 impl eframe::App for Sap1UI {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.handle_dropped_files(ctx);
+        self.run_clock(ctx);
+
+        egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
+            egui::MenuBar::new().ui(ui, |ui| {
+                ui.menu_button("File", |ui| {
+                    if ui.button("Open...").clicked() {
+                        self.open_program_dialog();
+                    }
+                    if ui.button("Save memory image...").clicked() {
+                        self.save_memory_dialog();
+                    }
+                    ui.separator();
+                    if ui.button("Load snapshot...").clicked() {
+                        self.load_snapshot_dialog();
+                    }
+                    if ui.button("Save snapshot...").clicked() {
+                        self.save_snapshot_dialog();
+                    }
+                    ui.separator();
+                    let mut recording = self.emulator.trace.is_some();
+                    if ui.checkbox(&mut recording, "Record waveform").changed() {
+                        if recording {
+                            self.emulator.start_trace();
+                        } else {
+                            self.emulator.trace = None;
+                        }
+                    }
+                    if ui
+                        .add_enabled(recording, egui::Button::new("Save waveform..."))
+                        .clicked()
+                    {
+                        self.save_waveform_dialog();
+                    }
+                });
+                ui.label(&self.file_status);
+                if let Some(trace) = &self.emulator.trace {
+                    let full = if trace.len() == vcd::MAX_TRACE_SAMPLES {
+                        " (full)"
+                    } else {
+                        ""
+                    };
+                    ui.label(format!("Recording: {} ticks{}", trace.len() - 1, full));
+                }
+            });
+        });
+
         egui::SidePanel::left("left_panel")
             .resizable(true)
             .default_width(150.0)
@@ -78,19 +520,140 @@ impl eframe::App for Sap1UI {
                     .show(ui, |ui| {
                         // Clock Display
                         ui.set_min_width(ui.available_width());
-                        ui.horizontal(|ui| {
-                            ui.label("Clock:");
-                            if ui.button("Step").clicked() {
-                                if !self.emulator.hlt {
-                                    self.emulator.clock_tick();
+                        let running = self.is_running();
+                        ui.add_enabled_ui(!self.program_mode, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Clock:");
+                                ui.add_enabled_ui(!running, |ui| {
+                                    if ui.button("Step").clicked() && !self.emulator.hlt {
+                                        self.emulator.clock_tick();
+                                    }
+                                    if ui.button("Step Instr").clicked() {
+                                        self.emulator.step_instruction();
+                                    }
+                                });
+                                if ui
+                                    .add_enabled(
+                                        !running && !self.emulator.hlt,
+                                        egui::Button::new("Run"),
+                                    )
+                                    .clicked()
+                                {
+                                    self.start_clock();
+                                    ctx.request_repaint();
+                                }
+                                if ui
+                                    .add_enabled(running, egui::Button::new("Pause"))
+                                    .clicked()
+                                {
+                                    self.stop_clock();
                                 }
+                                if ui.button("Reset").clicked() {
+                                    self.stop_clock();
+                                    self.emulator.reset();
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Back:");
+                                ui.add_enabled_ui(
+                                    !running && self.emulator.can_step_back(),
+                                    |ui| {
+                                        if ui.button("Step").clicked() {
+                                            self.emulator.step_back();
+                                        }
+                                        if ui.button("Step Instr").clicked() {
+                                            self.emulator.step_back_instruction();
+                                        }
+                                    },
+                                );
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Speed:");
+                                ui.add_enabled(
+                                    !self.max_speed,
+                                    egui::Slider::new(&mut self.clock_hz, 1.0..=1000.0)
+                                        .logarithmic(true)
+                                        .suffix(" Hz"),
+                                );
+                                ui.checkbox(&mut self.max_speed, "Max");
+                            });
+                            ui.checkbox(
+                                &mut self.emulator.settings.halt_on_contention,
+                                "Stop on bus contention",
+                            );
+                            ui.horizontal(|ui| {
+                                ui.label("Illegal opcodes:");
+                                let policy = &mut self.emulator.settings.illegal_opcode_policy;
+                                egui::ComboBox::from_id_salt("illegal_opcode_policy")
+                                    .selected_text(policy.name())
+                                    .show_ui(ui, |ui| {
+                                        for option in IllegalOpcodePolicy::ALL {
+                                            ui.selectable_value(policy, option, option.name());
+                                        }
+                                    });
+                            });
+                            if let Some(reason) = &self.emulator.break_reason {
+                                ui.colored_label(
+                                    egui::Color32::from_rgb(220, 60, 60),
+                                    format!("Stopped: {}", reason),
+                                );
                             }
-                            if ui.button("Run").clicked() {
-                                // TODO: Run until HLT
+                            if let Some(contention) = &self.emulator.last_contention {
+                                ui.colored_label(
+                                    egui::Color32::from_rgb(220, 180, 50),
+                                    format!(
+                                        "Warning: {} ({} ticks so far)",
+                                        contention, self.emulator.contention_count
+                                    ),
+                                );
                             }
                         });
                     });
 
+                egui::Frame::NONE
+                    .fill(egui::Color32::from_gray(40))
+                    .stroke(egui::Stroke::new(2.0, egui::Color32::from_gray(100)))
+                    .inner_margin(8.0)
+                    .outer_margin(4.0)
+                    .show(ui, |ui| {
+                        ui.set_min_width(ui.available_width());
+                        self.show_conditions(ui);
+                    });
+
+                ui.separator();
+
+                egui::Frame::NONE
+                    .fill(egui::Color32::from_gray(40))
+                    .stroke(egui::Stroke::new(2.0, egui::Color32::from_gray(100)))
+                    .inner_margin(8.0)
+                    .outer_margin(4.0)
+                    .show(ui, |ui| {
+                        // Program Mode DIP switches
+                        ui.set_min_width(ui.available_width());
+                        let mut program_mode = self.program_mode;
+                        if ui.checkbox(&mut program_mode, "Program Mode").changed() {
+                            self.set_program_mode(program_mode);
+                        }
+                        ui.add_enabled_ui(self.program_mode, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Address:");
+                                draw_switch_row(ui, &mut self.address_switches, LedColor::Address);
+                                ui.label(format!("({})", self.address_switches));
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Data:");
+                                draw_switch_row(ui, &mut self.data_switches, LedColor::Data);
+                                ui.label(format!("({})", self.data_switches));
+                                if ui.button("Write").clicked() {
+                                    self.emulator.write_memory(
+                                        self.address_switches as usize,
+                                        &[self.data_switches],
+                                    );
+                                }
+                            });
+                        });
+                    });
+
                 ui.separator();
 
                 egui::Frame::NONE
@@ -101,10 +664,11 @@ impl eframe::App for Sap1UI {
                     .show(ui, |ui| {
                         // Memory Address Register
                         ui.set_min_width(ui.available_width());
+                        let address = self.memory_address();
                         ui.horizontal(|ui| {
                             ui.label("Memory Address:");
-                            draw_byte_leds(ui, self.emulator.mar, LedColor::Address, 8);
-                            ui.label(format!("({})", self.emulator.mar));
+                            draw_byte_leds(ui, address, LedColor::Address, 8);
+                            ui.label(format!("({})", address));
                         });
                     });
 
@@ -115,20 +679,13 @@ impl eframe::App for Sap1UI {
                     .inner_margin(8.0)
                     .outer_margin(4.0)
                     .show(ui, |ui| {
+                        let value = self.emulator.memory[self.memory_address() as usize];
                         ui.horizontal(|ui| {
                             // RAM
                             ui.set_min_width(ui.available_width());
                             ui.label("RAM:");
-                            draw_byte_leds(
-                                ui,
-                                self.emulator.memory[self.emulator.mar as usize],
-                                LedColor::Data,
-                                8,
-                            );
-                            ui.label(format!(
-                                "({})",
-                                self.emulator.memory[self.emulator.mar as usize]
-                            ));
+                            draw_byte_leds(ui, value, LedColor::Data, 8);
+                            ui.label(format!("({})", value));
                         });
                     });
                 ui.separator();
@@ -173,7 +730,61 @@ impl eframe::App for Sap1UI {
                             ui.label("ROM Address:");
                             draw_byte_leds(ui, self.emulator.t_step, LedColor::Address, 3);
                             draw_byte_leds(ui, self.emulator.ir, LedColor::Address, 8);
+                            if let Some(name) = self.emulator.microcode.name(self.emulator.ir) {
+                                ui.label(name);
+                            }
+                        });
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.label("Microcode:");
+                            if ui
+                                .add_enabled(
+                                    self.microcode_path.is_some(),
+                                    egui::Button::new("Reload"),
+                                )
+                                .clicked()
+                            {
+                                self.reload_microcode();
+                            }
+                            if ui.button("Check").clicked() {
+                                self.microcode_issues =
+                                    Some(validator::validate(&self.emulator.microcode));
+                            }
+                            ui.label(&self.microcode_status);
                         });
+                        if let Some(issues) = &self.microcode_issues {
+                            let mut hide = false;
+                            let notes = issues.iter().filter(|issue| issue.is_note()).count();
+                            ui.horizontal(|ui| {
+                                ui.label(format!(
+                                    "{} problems found, {} notes",
+                                    issues.len() - notes,
+                                    notes
+                                ));
+                                hide = ui.small_button("Hide").clicked();
+                            });
+                            egui::ScrollArea::vertical()
+                                .id_salt("microcode_issues")
+                                .max_height(100.0)
+                                .show(ui, |ui| {
+                                    for issue in issues {
+                                        if issue.is_note() {
+                                            ui.colored_label(
+                                                egui::Color32::GRAY,
+                                                format!("Note: {}", issue),
+                                            );
+                                        } else {
+                                            ui.colored_label(
+                                                egui::Color32::from_rgb(220, 180, 50),
+                                                issue.to_string(),
+                                            );
+                                        }
+                                    }
+                                });
+                            if hide {
+                                self.microcode_issues = None;
+                            }
+                        }
                     });
 
                 egui::Frame::NONE
@@ -188,48 +799,106 @@ impl eframe::App for Sap1UI {
                                 ui.set_min_width(ui.available_width());
                                 let mut addr = 0;
                                 let mut skip_next = false;
+                                let mut clicked = None;
+                                let mut toggled = None;
+                                let mut watched = None;
+                                let current = self.memory_address() as usize;
 
                                 while addr < self.emulator.memory.len() {
-                                    let is_current = addr == self.emulator.mar as usize;
+                                    let is_current = addr == current;
                                     let arrow = if is_current { "->" } else { "  " };
                                     let color = if is_current {
                                         egui::Color32::from_rgb(220, 180, 50)
                                     } else {
                                         egui::Color32::GRAY
                                     };
-                                    if !skip_next {
+                                    let byte = self.emulator.memory[addr];
+                                    let description = if !skip_next {
                                         let (mnemonic, is_two_byte) =
                                             dissasemble_byte(&self.emulator.memory, addr);
+                                        skip_next = is_two_byte;
+                                        mnemonic
+                                    } else {
+                                        skip_next = false;
+                                        format!("{}", byte)
+                                    };
+
+                                    let editing = self
+                                        .memory_edit
+                                        .as_ref()
+                                        .is_some_and(|edit| edit.address == addr);
+                                    let has_breakpoint =
+                                        self.emulator.breakpoints.contains(&(addr as u8));
+                                    let watchpoint = self
+                                        .emulator
+                                        .watchpoints
+                                        .get(&(addr as u8))
+                                        .copied()
+                                        .unwrap_or_default();
+                                    if editing {
                                         ui.horizontal(|ui| {
+                                            breakpoint_marker(ui, has_breakpoint);
                                             ui.colored_label(color, arrow);
                                             ui.colored_label(color, format!("{:03}: ", addr));
-                                            ui.colored_label(
-                                                color,
-                                                format!("{:08b}", self.emulator.memory[addr]),
-                                            );
-                                            ui.colored_label(color, mnemonic);
+                                            self.show_memory_editor(ui);
                                         });
-                                        if is_two_byte {
-                                            skip_next = true;
-                                        }
                                     } else {
                                         ui.horizontal(|ui| {
-                                            ui.colored_label(color, arrow);
-                                            ui.colored_label(color, format!("{:03}: ", addr));
-                                            ui.colored_label(
-                                                color,
-                                                format!("{:08b}", self.emulator.memory[addr]),
-                                            );
-                                            ui.colored_label(
-                                                color,
-                                                format!("{}", self.emulator.memory[addr]),
-                                            );
-
-                                            skip_next = false;
+                                            if breakpoint_marker(ui, has_breakpoint).clicked() {
+                                                toggled = Some(addr as u8);
+                                            }
+                                            let row = ui
+                                                .horizontal(|ui| {
+                                                    ui.colored_label(color, arrow);
+                                                    ui.colored_label(
+                                                        color,
+                                                        format!("{:03}: ", addr),
+                                                    );
+                                                    ui.colored_label(
+                                                        color,
+                                                        format!("{:08b}", byte),
+                                                    );
+                                                    ui.colored_label(color, description);
+                                                    if watchpoint != Default::default() {
+                                                        ui.colored_label(
+                                                            egui::Color32::from_rgb(220, 60, 60),
+                                                            format!("[{}]", watchpoint),
+                                                        );
+                                                    }
+                                                })
+                                                .response
+                                                .interact(egui::Sense::click())
+                                                .on_hover_text(
+                                                    "Click to edit, right-click to watch",
+                                                );
+                                            if row.clicked() {
+                                                clicked = Some(addr);
+                                            }
+                                            row.context_menu(|ui| {
+                                                let mut changed = watchpoint;
+                                                ui.checkbox(&mut changed.read, "Stop on read (RO)");
+                                                ui.checkbox(
+                                                    &mut changed.write,
+                                                    "Stop on write (RI)",
+                                                );
+                                                if changed != watchpoint {
+                                                    watched = Some((addr as u8, changed));
+                                                }
+                                            });
                                         });
                                     }
                                     addr += 1;
                                 }
+
+                                if let Some(address) = clicked {
+                                    self.memory_edit = Some(MemoryEdit::new(address));
+                                }
+                                if let Some(address) = toggled {
+                                    self.emulator.toggle_breakpoint(address);
+                                }
+                                if let Some((address, watchpoint)) = watched {
+                                    self.emulator.set_watchpoint(address, watchpoint);
+                                }
                             });
                     });
             });
@@ -292,17 +961,9 @@ impl eframe::App for Sap1UI {
                                 ui.label(format!("({})", self.emulator.alu_out));
                                 ui.label("Flags:");
                                 ui.label("Z:");
-                                draw_led_bit(
-                                    ui,
-                                    if self.emulator.zf { true } else { false },
-                                    LedColor::Control.to_color32(),
-                                );
+                                draw_led_bit(ui, self.emulator.zf, LedColor::Control.to_color32());
                                 ui.label("C:");
-                                draw_led_bit(
-                                    ui,
-                                    if self.emulator.cf { true } else { false },
-                                    LedColor::Control.to_color32(),
-                                );
+                                draw_led_bit(ui, self.emulator.cf, LedColor::Control.to_color32());
                             });
                         });
                     });
@@ -360,9 +1021,7 @@ impl eframe::App for Sap1UI {
                                 let signals = self.emulator.control_word.to_array();
                                 let names = crate::emulator::ControlWord::signal_names();
 
-                                for (_i, (&bit, &name)) in
-                                    signals.iter().zip(names.iter()).enumerate()
-                                {
+                                for (&bit, &name) in signals.iter().zip(names.iter()) {
                                     ui.vertical(|ui| {
                                         draw_led_bit(ui, bit, LedColor::Control.to_color32());
                                         ui.label(name);
@@ -379,10 +1038,20 @@ impl eframe::App for Sap1UI {
             ui.heading("Bus");
             // Bus display area
             draw_byte_leds(ui, self.emulator.bus, LedColor::Address, 8);
+
+            ui.separator();
+            ui.heading("Statistics");
+            self.show_stats(ui);
         });
     }
 }
-pub fn run() {
+pub fn run(
+    microcode: MicrocodeRom,
+    microcode_path: Option<String>,
+    program: Option<Vec<u8>>,
+    snapshot: Option<Snapshot>,
+    settings: Settings,
+) {
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([800.0, 600.0]),
         ..Default::default()
@@ -391,7 +1060,15 @@ pub fn run() {
     let _ = eframe::run_native(
         "R-SAP-1 Emulator",
         options,
-        Box::new(|_cc| Ok(Box::new(Sap1UI::new()))),
+        Box::new(|_cc| {
+            Ok(Box::new(Sap1UI::new(
+                microcode,
+                microcode_path,
+                program,
+                snapshot,
+                settings,
+            )))
+        }),
     );
 }
 
@@ -412,6 +1089,27 @@ fn draw_led_bit(ui: &mut egui::Ui, bit: bool, color: egui::Color32) -> egui::Res
     response
 }
 
+// A row of LEDs that toggle their bit when clicked, like a DIP switch bank
+fn draw_switch_row(ui: &mut egui::Ui, value: &mut u8, color: LedColor) {
+    ui.horizontal(|ui| {
+        ui.spacing_mut().item_spacing.x = 4.0;
+        for i in (0..8).rev() {
+            let bit = (*value >> i) & 1 == 1;
+            let response = draw_led_bit(ui, bit, color.to_color32())
+                .interact(egui::Sense::click())
+                .on_hover_cursor(egui::CursorIcon::PointingHand);
+            if ui.is_rect_visible(response.rect) {
+                let stroke = egui::Stroke::new(1.0, egui::Color32::from_gray(120));
+                ui.painter()
+                    .circle_stroke(response.rect.center(), 7.0, stroke);
+            }
+            if response.clicked() {
+                *value ^= 1 << i;
+            }
+        }
+    });
+}
+
 fn draw_byte_leds(ui: &mut egui::Ui, value: u8, color: LedColor, num_bits: usize) {
     ui.horizontal(|ui| {
         ui.spacing_mut().item_spacing.x = 4.0;
@@ -422,38 +1120,45 @@ fn draw_byte_leds(ui: &mut egui::Ui, value: u8, color: LedColor, num_bits: usize
     });
 }
 
+// Clickable dot in front of a memory row, filled when PC stops at the address
+fn breakpoint_marker(ui: &mut egui::Ui, set: bool) -> egui::Response {
+    let (text, color) = if set {
+        ("●", egui::Color32::from_rgb(220, 60, 60))
+    } else {
+        ("○", egui::Color32::from_gray(70))
+    };
+    ui.add(egui::Label::new(egui::RichText::new(text).color(color)).sense(egui::Sense::click()))
+        .on_hover_text("Toggle breakpoint")
+}
+
 fn dissasemble_byte(memory: &[u8], address: usize) -> (String, bool) {
-    let byte = memory[address];
-    let opcode = byte >> 4;
-
-    match opcode {
-        0x0 => ("NOP".to_string(), false),
-        0x1 => ("LDA $".to_string(), true),
-        0x2 => ("LDA #".to_string(), true),
-        0x3 => ("LDB $".to_string(), true),
-        0x4 => ("LDB #".to_string(), true),
-        0x5 => ("ADD $".to_string(), true),
-        0x6 => ("ADD #".to_string(), true),
-        0x7 => ("SUB $".to_string(), true),
-        0x8 => ("SUB #".to_string(), true),
-        0x9 => ("STA".to_string(), true),
-        0xA => ("JMP".to_string(), true),
-        0xB => ("CMP $".to_string(), true),
-        0xC => ("CMP #".to_string(), true),
-        0xD => ("BNE".to_string(), true),
-        0xE => ("JPZ".to_string(), true),
-        0xF => match byte {
-            0xF0 => ("JPC".to_string(), true),
-            0xF1 => ("INC".to_string(), true),
-            0xF2 => ("DEC".to_string(), true),
-            0xF3 => ("OUT".to_string(), false),
-            0xFF => ("HLT".to_string(), false),
-            _ => ("???".to_string(), false),
-        },
-
-        _ => ("???".to_string(), false),
+    match Instruction::decode(memory[address]) {
+        Some(instruction) => (instruction.syntax(), instruction.size() == 2),
+        None => ("???".to_string(), false),
+    }
+}
+// Turn text typed into the memory view into bytes: a single value in binary
+// (eight 0/1 digits or 0b..), hex (0x..) or decimal, or one instruction
+fn parse_memory_entry(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("enter a value or instruction".to_string());
+    }
+
+    // Numbers start with a digit, mnemonics with a letter. Eight binary digits
+    // are read like the DIP switches, anything else like an assembler literal.
+    if !text.starts_with(|c: char| c.is_ascii_digit()) {
+        return assembler::assemble_line(text);
     }
+    let byte = if text.len() == 8 && text.chars().all(|c| c == '0' || c == '1') {
+        u8::from_str_radix(text, 2).ok()
+    } else {
+        assembler::parse_number(text).and_then(|value| u8::try_from(value).ok())
+    };
+    byte.map(|byte| vec![byte])
+        .ok_or_else(|| format!("'{}' is not a byte", text))
 }
+
 fn decode_t_step(t_step: u8) -> u8 {
     if t_step == 0 {
         0b00000000
@@ -0,0 +1,183 @@
+use crate::emulator::ControlWord;
+use crate::microcode::{FLAG_COMBINATIONS, MicrocodeRom, STEPS};
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    // Nothing is programmed after the fetch cycle, so the opcode runs empty
+    // control words until the T counter runs out
+    UnusedOpcode,
+    // Neither PR nor HLT in T0-T7, the instruction needs more steps than the
+    // 3-bit T counter has
+    MissingPr,
+    // More than one of CO, RO, AO, EO in the same step
+    BusContention(Vec<&'static str>),
+    // RAM is written before the instruction loaded MAR, so it lands on the
+    // address the opcode was fetched from
+    RiWithoutMi,
+    // J is asserted but no flag input ever adds FLG, so the jump is never taken
+    JumpWithoutFlg,
+}
+
+impl Problem {
+    // Worth knowing but harmless: an unused opcode behaves like a slow NOP
+    // under the default illegal opcode policy, so the stock table has some
+    pub fn is_note(&self) -> bool {
+        matches!(self, Problem::UnusedOpcode)
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::UnusedOpcode => write!(f, "unused opcode, only the fetch cycle is programmed"),
+            Problem::MissingPr => write!(f, "no PR or HLT before the T counter runs past T7"),
+            Problem::BusContention(drivers) => {
+                write!(f, "bus contention between {}", drivers.join(", "))
+            }
+            Problem::RiWithoutMi => write!(f, "RI without an MI earlier in the instruction"),
+            Problem::JumpWithoutFlg => write!(f, "J without FLG for any flag input"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub opcodes: RangeInclusive<u8>,
+    pub t_step: Option<u8>,
+    // Bit (cf << 1 | zf) is set for every flag input the problem shows up with
+    pub flags: u8,
+    pub problem: Problem,
+}
+
+const ALL_FLAGS: u8 = (1 << FLAG_COMBINATIONS) - 1;
+
+impl Issue {
+    pub fn is_note(&self) -> bool {
+        self.problem.is_note()
+    }
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (first, last) = (*self.opcodes.start(), *self.opcodes.end());
+        if first == last {
+            write!(f, "0x{:02X}", first)?;
+        } else {
+            write!(f, "0x{:02X}-0x{:02X}", first, last)?;
+        }
+        if let Some(step) = self.t_step {
+            write!(f, " T{}", step)?;
+        }
+        if self.flags != ALL_FLAGS {
+            // Same qualifiers as the microcode text format
+            let inputs: Vec<String> = (0..FLAG_COMBINATIONS)
+                .filter(|&flags| self.flags & (1 << flags) != 0)
+                .map(|flags| {
+                    let carry = if flags & 0b10 != 0 { "C" } else { "!C" };
+                    let zero = if flags & 0b01 != 0 { "Z" } else { "!Z" };
+                    format!("{} {}", carry, zero)
+                })
+                .collect();
+            write!(f, " [{}]", inputs.join(", "))?;
+        }
+        write!(f, ": {}", self.problem)
+    }
+}
+
+// Check every opcode, T-step and flag input of the ROM. Issues shared by a run
+// of neighbouring opcodes are reported once for the whole range.
+pub fn validate(rom: &MicrocodeRom) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = Vec::new();
+    for opcode in 0..=255 {
+        for (t_step, flags, problem) in check_opcode(rom, opcode) {
+            let previous = issues.iter_mut().find(|issue| {
+                *issue.opcodes.end() as usize + 1 == opcode as usize
+                    && issue.t_step == t_step
+                    && issue.flags == flags
+                    && issue.problem == problem
+            });
+            match previous {
+                Some(issue) => issue.opcodes = *issue.opcodes.start()..=opcode,
+                None => issues.push(Issue {
+                    opcodes: opcode..=opcode,
+                    t_step,
+                    flags,
+                    problem,
+                }),
+            }
+        }
+    }
+    issues
+}
+
+// Problems of one opcode as (T-step, flag inputs, problem)
+fn check_opcode(rom: &MicrocodeRom, opcode: u8) -> Vec<(Option<u8>, u8, Problem)> {
+    let words = |cf: bool, zf: bool| -> Vec<ControlWord> {
+        (0..STEPS as u8)
+            .map(|step| rom.get(opcode, step, cf, zf))
+            .collect()
+    };
+
+    // Steps where some flag input takes the jump
+    let mut jump_taken = [false; STEPS];
+    for flags in 0..FLAG_COMBINATIONS {
+        for (step, word) in words(flags & 0b10 != 0, flags & 0b01 != 0)
+            .iter()
+            .enumerate()
+        {
+            jump_taken[step] |= word.J && word.FLG;
+        }
+    }
+
+    let mut found: Vec<(Option<u8>, u8, Problem)> = Vec::new();
+    for flags in 0..FLAG_COMBINATIONS {
+        let words = words(flags & 0b10 != 0, flags & 0b01 != 0);
+        let mut report = |t_step: Option<u8>, problem: Problem| match found
+            .iter_mut()
+            .find(|(step, _, known)| *step == t_step && *known == problem)
+        {
+            Some((_, mask, _)) => *mask |= 1 << flags,
+            None => found.push((t_step, 1 << flags, problem)),
+        };
+
+        // Steps after PR or HLT are never reached
+        let end = words.iter().position(|word| word.PR || word.HLT);
+        let reachable = &words[..end.map_or(STEPS, |end| end + 1)];
+        // The instruction's own steps start after it is loaded into IR
+        let body = reachable
+            .iter()
+            .rposition(|word| word.II)
+            .map_or(0, |step| step + 1);
+
+        if end.is_none() {
+            if words[body..]
+                .iter()
+                .all(|word| *word == ControlWord::default())
+            {
+                report(None, Problem::UnusedOpcode);
+            } else {
+                report(None, Problem::MissingPr);
+            }
+        }
+
+        let mut mar_loaded = false;
+        for (step, word) in reachable.iter().enumerate() {
+            let t_step = Some(step as u8);
+            let drivers = word.bus_drivers();
+            if drivers.len() > 1 {
+                report(t_step, Problem::BusContention(drivers));
+            }
+            if step >= body {
+                if word.RI && !mar_loaded {
+                    report(t_step, Problem::RiWithoutMi);
+                }
+                mar_loaded |= word.MI;
+            }
+            if word.J && !word.FLG && !jump_taken[step] {
+                report(t_step, Problem::JumpWithoutFlg);
+            }
+        }
+    }
+    found
+}
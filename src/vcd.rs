@@ -0,0 +1,152 @@
+use crate::emulator::{ControlWord, Sap1};
+
+// Recording stops once this many ticks are held, about 32 MB
+pub const MAX_TRACE_SAMPLES: usize = 1 << 20;
+
+// Dump time units per clock tick: the clock rises at 0 and falls at 5
+const TICK_TIME: u64 = 10;
+
+// The signals visible on a logic analyzer after one clock tick
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraceSample {
+    pub control_word: ControlWord,
+    pub bus: u8,
+    pub pc: u8,
+    pub mar: u8,
+    pub ir: u8,
+    pub reg_a: u8,
+    pub reg_b: u8,
+    pub alu_out: u8,
+    pub output: u8,
+    pub t_step: u8,
+    pub cf: bool,
+    pub zf: bool,
+}
+
+impl TraceSample {
+    pub fn capture(sap1: &Sap1) -> Self {
+        TraceSample {
+            control_word: sap1.control_word,
+            bus: sap1.bus,
+            pc: sap1.pc,
+            mar: sap1.mar,
+            ir: sap1.ir,
+            reg_a: sap1.reg_a,
+            reg_b: sap1.reg_b,
+            alu_out: sap1.alu_out,
+            output: sap1.output,
+            t_step: sap1.t_step,
+            cf: sap1.cf,
+            zf: sap1.zf,
+        }
+    }
+
+    // (name, width, value) of every traced signal, in dump order
+    fn signals(&self) -> Vec<(&'static str, u32, u8)> {
+        let mut signals = vec![
+            ("bus", 8, self.bus),
+            ("pc", 8, self.pc),
+            ("mar", 8, self.mar),
+            ("ir", 8, self.ir),
+            ("a", 8, self.reg_a),
+            ("b", 8, self.reg_b),
+            ("alu", 8, self.alu_out),
+            ("out", 8, self.output),
+            ("t_step", 3, self.t_step),
+            ("cf", 1, self.cf as u8),
+            ("zf", 1, self.zf as u8),
+        ];
+        for (name, bit) in ControlWord::signal_names()
+            .into_iter()
+            .zip(self.control_word.to_array())
+        {
+            signals.push((name, 1, bit as u8));
+        }
+        signals
+    }
+}
+
+// Short identifier for the n-th variable, using the printable ASCII range
+fn identifier(mut n: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (n % 94) as u8) as char);
+        n /= 94;
+        if n == 0 {
+            return id;
+        }
+        n -= 1;
+    }
+}
+
+fn value_change(width: u32, value: u8, id: &str) -> String {
+    if width == 1 {
+        format!("{}{}\n", value, id)
+    } else {
+        let value = value as u32 & ((1 << width) - 1);
+        format!("b{:0width$b} {}\n", value, id, width = width as usize)
+    }
+}
+
+// Render a trace as a Value Change Dump. Each sample is one clock period,
+// registers are dumped as vectors and the control signals as single wires.
+pub fn to_vcd(samples: &[TraceSample]) -> String {
+    let mut text = String::new();
+    text.push_str("$version R-SAP-1 emulator $end\n");
+    text.push_str("$timescale 1us $end\n");
+    text.push_str("$scope module sap1 $end\n");
+    text.push_str(&format!("$var wire 1 {} clk $end\n", identifier(0)));
+
+    let names = TraceSample::default().signals();
+    let control_start = names.len() - ControlWord::SIGNALS;
+    for (i, &(name, width, _)) in names.iter().enumerate() {
+        if i == control_start {
+            text.push_str("$scope module control $end\n");
+        }
+        let range = if width > 1 {
+            format!(" [{}:0]", width - 1)
+        } else {
+            String::new()
+        };
+        text.push_str(&format!(
+            "$var wire {} {} {}{} $end\n",
+            width,
+            identifier(i + 1),
+            name,
+            range
+        ));
+    }
+    text.push_str("$upscope $end\n");
+    text.push_str("$upscope $end\n");
+    text.push_str("$enddefinitions $end\n");
+
+    let mut previous: Option<Vec<(&'static str, u32, u8)>> = None;
+    for (tick, sample) in samples.iter().enumerate() {
+        let time = tick as u64 * TICK_TIME;
+        text.push_str(&format!("#{}\n", time));
+        if previous.is_none() {
+            text.push_str("$dumpvars\n");
+        }
+        text.push_str(&value_change(1, 1, &identifier(0)));
+        let signals = sample.signals();
+        for (i, &(_, width, value)) in signals.iter().enumerate() {
+            let changed = previous
+                .as_ref()
+                .is_none_or(|previous| previous[i].2 != value);
+            if changed {
+                text.push_str(&value_change(width, value, &identifier(i + 1)));
+            }
+        }
+        if previous.is_none() {
+            text.push_str("$end\n");
+        }
+        text.push_str(&format!("#{}\n", time + TICK_TIME / 2));
+        text.push_str(&value_change(1, 0, &identifier(0)));
+        previous = Some(signals);
+    }
+    text
+}
+
+pub fn save(path: &str, samples: &[TraceSample]) -> Result<(), String> {
+    std::fs::write(path, to_vcd(samples)).map_err(|e| format!("{}: {}", path, e))
+}